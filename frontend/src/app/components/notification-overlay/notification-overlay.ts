@@ -11,14 +11,68 @@ import { WebviewWindow } from '@tauri-apps/api/webviewWindow';
 
 type NotificationPayload = {
   id: string;
-  thread_id: string;
+  account: string;
+  threadId: string;
   subject: string;
   snippet?: string | null;
   sender?: string | null;
   recipient?: string | null;
+  from?: EmailAddress | null;
+  to?: EmailAddress[];
+  cc?: EmailAddress[];
+  replyTo?: EmailAddress[];
   receivedAt?: string | null;
+  // Уже в локальном времени, считаются в Rust
+  receivedLocal?: string | null;
+  receivedRelative?: string | null;
   url: string;
+  hasBody?: boolean;
+  // Архив, корзина, важность и метки; без них (IMAP) остаётся только звёздочка
+  mailboxActions?: boolean;
+  // Сводка «и ещё N писем» или дайджест: только перейти, закрыть или отложить
+  summary?: boolean;
+  attachments?: Attachment[];
+  // Более ранние письма той же цепочки; показывается самое новое
+  mergedIds?: string[];
+  soundPath?: string | null;
+  priority?: boolean;
+  vip?: boolean;
+  // Нерабочее время по расписанию: без звука
+  silent?: boolean;
+  // Загружается отдельно через get_message_body
   body?: string | null;
+  blockedResources?: number;
+  imagesAllowed?: boolean;
+};
+
+type EmailAddress = {
+  name?: string;
+  email: string;
+};
+
+type MailLabel = {
+  id: string;
+  name: string;
+};
+
+type ReplyTemplate = {
+  name: string;
+  body: string;
+};
+
+type Attachment = {
+  id: string;
+  filename: string;
+  mimeType: string;
+  size: number;
+};
+
+type MessageBodyPayload = {
+  html: string;
+  blockedResources: number;
+  trackingPixels: number;
+  sender?: string | null;
+  imagesAllowed: boolean;
 };
 
 @Component({
@@ -60,9 +114,18 @@ export class NotificationOverlay implements OnInit, OnDestroy {
 
     this.unlistenFns.push(await this.ipc.on('gmail://notification', async (n: NotificationPayload) => {
       console.debug('[gmail notification]', JSON.stringify(n, null, 2));
+      // Новое письмо той же цепочки не должно стирать начатый ответ
+      if (this.notification()?.threadId !== n.threadId) {
+        this.reply.set(null);
+        this.replyError.set(null);
+      }
+      this.actionError.set(null);
       this.notification.set(n);
       this.visible.set(true);
-      await this.playSound();
+      await this.playSound(n);
+      if (this.isExpanded()) {
+        await this.loadBody();
+      }
     }));
     this.unlistenFns.push(await this.ipc.on('gmail://settings', (s: any) => {
       this.settings = s;
@@ -111,16 +174,93 @@ export class NotificationOverlay implements OnInit, OnDestroy {
   }
 
   async markRead() {
+    await this.act('mark_message_read');
+  }
+
+  labels = signal<MailLabel[] | null>(null);
+  actionError = signal<string | null>(null);
+
+  // mark_message_read, archive_message, trash_message, star_message, mark_important, apply_label, snooze_message.
+  // Окно прячем только после успеха: при ошибке уведомление остаётся на экране и в очереди
+  async act(command: string, args: Record<string, unknown> = {}) {
     const n = this.notification();
     if (!n) return;
-    console.log('notification', n);
-    this.notification.set(null);
-    this.visible.set(false);
-    await this.hideWindow();
+    this.labels.set(null);
+    this.snoozeOptions.set(false);
+    this.actionError.set(null);
+    try {
+      await this.ipc.invoke(command, { messageId: n.id, ...args });
+    } catch (error) {
+      console.error(`failed to ${command}`, error);
+      this.actionError.set(String(error));
+      return;
+    }
+    // Следующее уведомление могло прийти раньше ответа команды — его не прячем
+    if (this.notification() === n) {
+      this.notification.set(null);
+      this.visible.set(false);
+      await this.hideWindow();
+    }
+  }
+
+  async toggleLabels() {
+    const n = this.notification();
+    if (!n || this.labels()) {
+      this.labels.set(null);
+      return;
+    }
     try {
-      await this.ipc.invoke('mark_message_read', { messageId: n.id });
+      this.labels.set(await this.ipc.invoke<MailLabel[]>('list_labels', { messageId: n.id }));
     } catch (error) {
-      console.error('failed to mark read', error);
+      console.error('failed to load labels', error);
+    }
+  }
+
+  reply = signal<string | null>(null);
+  replyError = signal<string | null>(null);
+  sending = signal<boolean>(false);
+  templates = signal<ReplyTemplate[]>([]);
+
+  async toggleReply() {
+    this.reply.set(this.reply() === null ? '' : null);
+    this.replyError.set(null);
+    if (this.reply() !== null) {
+      try {
+        this.templates.set(await this.ipc.invoke<ReplyTemplate[]>('list_reply_templates'));
+      } catch (error) {
+        console.error('failed to load reply templates', error);
+      }
+    }
+  }
+
+  // Шаблон подставляется на стороне Rust из полей уведомления
+  async sendTemplate(template: ReplyTemplate) {
+    await this.submitReply('send_template_reply', { template: template.name });
+  }
+
+  // Окно прячем только после отправки: при ошибке текст ответа не должен пропасть
+  async sendReply() {
+    const body = this.reply();
+    if (!body?.trim()) return;
+    await this.submitReply('send_reply', { body });
+  }
+
+  private async submitReply(command: string, args: Record<string, unknown>) {
+    const n = this.notification();
+    if (!n || this.sending()) return;
+    this.sending.set(true);
+    this.replyError.set(null);
+    try {
+      await this.ipc.invoke(command, { messageId: n.id, ...args });
+      this.reply.set(null);
+      this.notification.set(null);
+      this.visible.set(false);
+      await this.hideWindow();
+    } catch (error) {
+      console.error('failed to send reply', error);
+      this.replyError.set(String(error));
+    } finally {
+      this.sending.set(false);
     }
   }
 
@@ -136,17 +276,45 @@ export class NotificationOverlay implements OnInit, OnDestroy {
     }
   }
 
-  async snooze() {
+  snoozeOptions = signal<boolean>(false);
+  // Местное время для «до конца встречи», HH:MM
+  snoozeTime = signal<string>('');
+  readonly snoozeHour = { type: 'minutes', minutes: 60 };
+  readonly snoozeTomorrow = { type: 'tomorrow', time: '09:00' };
+
+  toggleSnoozeOptions() {
+    this.snoozeOptions.update(open => !open);
+  }
+
+  // Без target — на время из настроек
+  async snooze(target?: unknown) {
     this.notification.set(null);
     this.visible.set(false);
+    this.snoozeOptions.set(false);
     await this.hideWindow();
     try {
-      await this.ipc.invoke('snooze');
+      await this.ipc.invoke('snooze', target ? { target } : {});
     } catch (error) {
       console.error('failed to snooze', error);
     }
   }
 
+  // Ближайшее наступление выбранного времени: сегодня или, если уже прошло, завтра
+  async snoozeUntilTime(onlyThis: boolean) {
+    const [hours, minutes] = this.snoozeTime().split(':').map(Number);
+    if (Number.isNaN(hours) || Number.isNaN(minutes)) return;
+    const at = new Date();
+    at.setHours(hours, minutes, 0, 0);
+    if (at.getTime() <= Date.now()) at.setDate(at.getDate() + 1);
+    const target = { type: 'until', at: at.toISOString() };
+    this.snoozeOptions.set(false);
+    if (onlyThis) {
+      await this.act('snooze_message', { target });
+    } else {
+      await this.snooze(target);
+    }
+  }
+
   private async restoreCurrent() {
     try {
       const current = await this.ipc.invoke<NotificationPayload | null>('current_notification');
@@ -160,10 +328,14 @@ export class NotificationOverlay implements OnInit, OnDestroy {
     }
   }
 
-  private async playSound() {
-    if (!this.settings?.sound_enabled || !this.settings?.sound_path) return;
+  private async playSound(n: NotificationPayload) {
+    // Мелодия из правила, затем VIP и мелодия аккаунта, иначе играем общую
+    const account = (this.settings?.accounts ?? []).find((a: any) => a.email === n.account);
+    const vipSound = n.vip ? this.settings?.vip_sound_path : null;
+    const soundPath = n.soundPath || vipSound || account?.sound_path || this.settings?.sound_path;
+    if (!this.settings?.sound_enabled || n.silent || !soundPath) return;
     try {
-      const src = await this.resolveSoundSource(this.settings.sound_path);
+      const src = await this.resolveSoundSource(soundPath);
       if (!src) {
         return;
       }
@@ -232,9 +404,66 @@ export class NotificationOverlay implements OnInit, OnDestroy {
     if (!body) {
       return '';
     }
+    // Тело уже очищено в Rust (sanitise_html), Angular-санитайзер срезал бы inline-стили писем
     return this.sanitizer.bypassSecurityTrustHtml(body);
   }
 
+  // Уведомление приходит без тела, загружаем его при разворачивании
+  private async loadBody(force = false) {
+    const n = this.notification();
+    if (!n || (n.body && !force) || !n.hasBody) return;
+    try {
+      // HTML приходит уже очищенным на стороне Rust
+      const body = await this.ipc.invoke<MessageBodyPayload | null>('get_message_body', { messageId: n.id });
+      if (this.notification()?.id === n.id) {
+        this.notification.set({
+          ...n,
+          body: body?.html ?? null,
+          blockedResources: body?.blockedResources ?? 0,
+          imagesAllowed: body?.imagesAllowed ?? false,
+        });
+      }
+    } catch (error) {
+      console.error('failed to load message body', error);
+    }
+  }
+
+  formatAddresses(addresses: EmailAddress[]): string {
+    return addresses.map((a) => (a.name ? `${a.name} <${a.email}>` : a.email)).join(', ');
+  }
+
+  formatSize(bytes: number): string {
+    if (bytes < 1024) return `${bytes} Б`;
+    if (bytes < 1024 * 1024) return `${Math.round(bytes / 1024)} КБ`;
+    return `${(bytes / 1024 / 1024).toFixed(1)} МБ`;
+  }
+
+  async downloadAttachment(attachment: Attachment) {
+    const n = this.notification();
+    if (!n) return;
+    try {
+      const path = await this.ipc.invoke<string>('download_attachment', {
+        messageId: n.id,
+        attachmentId: attachment.id,
+        filename: attachment.filename,
+      });
+      console.info('attachment saved to', path);
+    } catch (error) {
+      console.error('failed to download attachment', error);
+    }
+  }
+
+  async allowImages() {
+    const n = this.notification();
+    if (!n?.sender) return;
+    try {
+      await this.ipc.invoke('set_sender_images', { sender: n.sender, allowed: true });
+      await this.loadBody(true);
+    } catch (error) {
+      console.error('failed to allow images', error);
+    }
+  }
+
   async toggleExpand() {
     const tauriWindow: WebviewWindow | any = getCurrentWindow();
     const isCurrentlyExpanded = this.isExpanded();
@@ -285,6 +514,7 @@ export class NotificationOverlay implements OnInit, OnDestroy {
 
       this.isExpanded.set(true);
       console.log('toggleExpand: window expanded, isExpanded=', this.isExpanded());
+      await this.loadBody();
     } else {
       console.log('toggleExpand: collapsing window');
       // Сворачиваем окно обратно
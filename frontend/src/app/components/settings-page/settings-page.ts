@@ -1,4 +1,4 @@
-import { Component, OnInit, signal } from '@angular/core';
+import { Component, OnDestroy, OnInit, signal } from '@angular/core';
 import { CommonModule } from '@angular/common';
 import { FormsModule } from '@angular/forms';
 import { Settings, VoicePreset } from '../../services/settings';
@@ -9,6 +9,28 @@ import { MatCheckboxModule } from '@angular/material/checkbox';
 import { MatButtonModule } from '@angular/material/button';
 import { MatSelectModule, MatSelectChange } from '@angular/material/select';
 import { MatSliderModule } from '@angular/material/slider';
+import { UnlistenFn } from '@tauri-apps/api/event';
+
+type RuleVerdict = {
+  messageId: string;
+  account: string;
+  subject: string;
+  sender?: string | null;
+  rule?: string | null;
+  action?: { type: string; path?: string } | null;
+};
+
+type SnoozeStatus = {
+  until?: string | null;
+  messages: { messageId: string; account: string; subject: string; until: string }[];
+};
+
+type DegradedPayload = {
+  account: string;
+  degraded: boolean;
+  reason?: string | null;
+  retryAt?: string | null;
+};
 
 @Component({
   selector: 'app-settings-page',
@@ -16,11 +38,27 @@ import { MatSliderModule } from '@angular/material/slider';
   templateUrl: './settings-page.component.html',
   styleUrls: ['./settings-page.component.css']
 })
-export class SettingsPage implements OnInit {
+export class SettingsPage implements OnInit, OnDestroy {
   authorised = signal<boolean>(false);
+  degradedAccounts = signal<DegradedPayload[]>([]);
+  private unlistenFns: UnlistenFn[] = [];
   busy = signal<boolean>(false);
   voicePresets = signal<VoicePreset[]>([]);
   selectedVoicePreset = signal<string | null>(null);
+  ruleVerdicts = signal<RuleVerdict[] | null>(null);
+  snoozeStatus = signal<SnoozeStatus | null>(null);
+  // Правила редактируются как JSON: условий и действий слишком много для отдельных полей
+  rulesJson = '[]';
+  vipSendersText = '';
+  readonly weekdays = [
+    { value: 'Mon', label: 'Пн' },
+    { value: 'Tue', label: 'Вт' },
+    { value: 'Wed', label: 'Ср' },
+    { value: 'Thu', label: 'Чт' },
+    { value: 'Fri', label: 'Пт' },
+    { value: 'Sat', label: 'Сб' },
+    { value: 'Sun', label: 'Вс' }
+  ];
   model: any = {
     poll_interval_secs: 60,
     sound_enabled: true,
@@ -28,6 +66,7 @@ export class SettingsPage implements OnInit {
     playback_volume: 0.7,
     auto_launch: true,
     gmail_query: 'in:inbox is:unread',
+    max_messages_per_poll: 50,
     oauth_client_id: '',
     oauth_client_secret: '',
     snooze_duration_mins: 20,
@@ -35,8 +74,28 @@ export class SettingsPage implements OnInit {
     notification_height: 150,
     notification_opacity: 0.95,
     notification_expanded_width: 800,
-    notification_expanded_height: 600
+    notification_expanded_height: 600,
+    mail_backend: 'gmail',
+    received_at_source: 'internal_date',
+    quick_reply_enabled: false,
+    reply_templates: [],
+    schedule: {
+      enabled: false,
+      mode: 'silence',
+      windows: []
+    },
+    accounts: [],
+    imap: {
+      host: '',
+      port: 993,
+      security: 'tls',
+      username: '',
+      mailbox: 'INBOX',
+      use_idle: true,
+      web_url: null
+    }
   };
+  imapPassword = '';
 
   constructor(private readonly settingsSvc: Settings, private readonly ipc: Ipc) { }
 
@@ -44,8 +103,19 @@ export class SettingsPage implements OnInit {
     const state = await this.settingsSvc.initialise();
     this.authorised.set(state.authorised);
     this.model = { ...this.model, ...state.settings };
+    this.rulesJson = JSON.stringify(this.model.rules ?? [], null, 2);
+    this.vipSendersText = (this.model.vip_senders ?? []).join('\n');
     await this.loadVoicePresets();
     this.syncVoicePresetSelection();
+    await this.loadSnoozeStatus();
+    this.unlistenFns.push(await this.ipc.on('gmail://degraded', (p: DegradedPayload) => {
+      const others = this.degradedAccounts().filter(item => item.account !== p.account);
+      this.degradedAccounts.set(p.degraded ? [...others, p] : others);
+    }));
+  }
+
+  ngOnDestroy() {
+    this.unlistenFns.forEach(u => u());
   }
 
   async connect() {
@@ -74,18 +144,94 @@ export class SettingsPage implements OnInit {
     }
   }
 
+  async removeAccount(email: string) {
+    this.busy.set(true);
+    try {
+      await this.ipc.invoke('revoke', { account: email });
+      const state = await this.settingsSvc.initialise();
+      this.authorised.set(state.authorised);
+      this.model = { ...this.model, ...state.settings };
+    } catch (e) {
+      alert('Не удалось отключить аккаунт: ' + e);
+    } finally {
+      this.busy.set(false);
+    }
+  }
+
+  readonly templateVariables = '{sender_name}, {sender_email}, {subject}, {date}, {account}';
+
+  addTemplate() {
+    this.model.reply_templates = [...(this.model.reply_templates ?? []), { name: '', body: '' }];
+  }
+
+  removeTemplate(index: number) {
+    this.model.reply_templates = this.model.reply_templates.filter((_: any, i: number) => i !== index);
+  }
+
+  addWorkWindow() {
+    const windows = this.model.schedule.windows ?? [];
+    this.model.schedule.windows = [...windows, { days: ['Mon', 'Tue', 'Wed', 'Thu', 'Fri'], start: '09:00', end: '19:00' }];
+  }
+
+  removeWorkWindow(index: number) {
+    this.model.schedule.windows = this.model.schedule.windows.filter((_: any, i: number) => i !== index);
+  }
+
+  async loadSnoozeStatus() {
+    try {
+      this.snoozeStatus.set(await this.ipc.invoke<SnoozeStatus>('snooze_status'));
+    } catch (e) {
+      console.warn('Не удалось получить состояние паузы', e);
+    }
+  }
+
+  async cancelSnooze() {
+    try {
+      await this.ipc.invoke('cancel_snooze');
+    } finally {
+      await this.loadSnoozeStatus();
+    }
+  }
+
   async checkNow() {
     this.busy.set(true);
     try {
       await this.ipc.invoke('check_now');
     } catch (e) {
       alert('Не удалось выполнить проверку: ' + e);
+    } finally {
+      this.busy.set(false);
+      await this.loadSnoozeStatus();
+    }
+  }
+
+  private parseRules(): any[] | null {
+    try {
+      const rules = JSON.parse(this.rulesJson || '[]');
+      if (Array.isArray(rules)) return rules;
+      alert('Правила должны быть JSON-массивом');
+    } catch (e) {
+      alert('Правила — неверный JSON: ' + e);
+    }
+    return null;
+  }
+
+  async dryRunRules() {
+    const rules = this.parseRules();
+    if (!rules) return;
+    this.busy.set(true);
+    try {
+      this.ruleVerdicts.set(await this.ipc.invoke<RuleVerdict[]>('dry_run_rules', { rules }));
+    } catch (e) {
+      alert('Не удалось проверить правила: ' + e);
     } finally {
       this.busy.set(false);
     }
   }
 
   async save() {
+    const rules = this.parseRules();
+    if (!rules) return;
     this.busy.set(true);
     try {
       const update = {
@@ -94,6 +240,7 @@ export class SettingsPage implements OnInit {
         sound_path: this.model.sound_path || null,
         auto_launch: !!this.model.auto_launch,
         gmail_query: this.model.gmail_query,
+        max_messages_per_poll: Number(this.model.max_messages_per_poll),
         oauth_client_id: this.model.oauth_client_id,
         oauth_client_secret: this.model.oauth_client_secret || null,
         playback_volume: Number(String(this.model.playback_volume).replace(',', '.')),
@@ -102,10 +249,34 @@ export class SettingsPage implements OnInit {
         notification_height: Number(this.model.notification_height),
         notification_opacity: Number(String(this.model.notification_opacity).replace(',', '.')),
         notification_expanded_width: Number(this.model.notification_expanded_width),
-        notification_expanded_height: Number(this.model.notification_expanded_height)
+        notification_expanded_height: Number(this.model.notification_expanded_height),
+        mail_backend: this.model.mail_backend,
+        received_at_source: this.model.received_at_source,
+        quick_reply_enabled: !!this.model.quick_reply_enabled,
+        reply_templates: (this.model.reply_templates ?? []).map((t: any) => ({ name: t.name, body: t.body })),
+        rules,
+        vip_senders: this.vipSendersText.split('\n').map(line => line.trim()).filter(line => line),
+        vip_sound_path: this.model.vip_sound_path || null,
+        schedule: {
+          enabled: !!this.model.schedule.enabled,
+          mode: this.model.schedule.mode,
+          windows: (this.model.schedule.windows ?? []).map((w: any) => ({ days: w.days ?? [], start: w.start, end: w.end }))
+        },
+        accounts: (this.model.accounts ?? []).map((a: any) => ({
+          email: a.email,
+          gmail_query: a.gmail_query,
+          sound_path: a.sound_path || null
+        })),
+        imap: {
+          ...this.model.imap,
+          port: Number(this.model.imap.port),
+          web_url: this.model.imap.web_url || null
+        }
       };
       const saved = await this.settingsSvc.update(update);
       this.model = { ...this.model, ...saved };
+      this.rulesJson = JSON.stringify(this.model.rules ?? [], null, 2);
+      this.vipSendersText = (this.model.vip_senders ?? []).join('\n');
       this.syncVoicePresetSelection();
     } catch (e) {
       alert('Не удалось сохранить: ' + e);
@@ -114,6 +285,19 @@ export class SettingsPage implements OnInit {
     }
   }
 
+  async saveImapPassword() {
+    this.busy.set(true);
+    try {
+      await this.save();
+      await this.ipc.invoke('set_imap_password', { password: this.imapPassword });
+      this.imapPassword = '';
+    } catch (e) {
+      alert('Не удалось сохранить пароль IMAP: ' + e);
+    } finally {
+      this.busy.set(false);
+    }
+  }
+
   async loadVoicePresets() {
     try {
       const presets = await this.settingsSvc.voicePresets();
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+
+const BASE_DELAY: Duration = Duration::from_secs(15);
+const MAX_DELAY: Duration = Duration::from_secs(30 * 60);
+
+/// Состояние отступа после временных сбоев API, общее для всех запросов аккаунта.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    failures: u32,
+    until: Option<DateTime<Utc>>,
+    reason: Option<String>,
+}
+
+impl Backoff {
+    /// Момент, до которого запросы не отправляем, и причина последнего сбоя.
+    pub fn blocked(&self, now: DateTime<Utc>) -> Option<(DateTime<Utc>, String)> {
+        match self.until {
+            Some(until) if until > now => Some((until, self.reason.clone().unwrap_or_default())),
+            _ => None,
+        }
+    }
+
+    /// Фиксирует сбой и возвращает время следующей попытки.
+    pub fn fail(&mut self, now: DateTime<Utc>, retry_after: Option<Duration>, reason: String) -> DateTime<Utc> {
+        self.failures = self.failures.saturating_add(1);
+        let delay = next_delay(self.failures, retry_after, rand::thread_rng().gen_range(0.8..1.2));
+        let until = now + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::zero());
+        self.until = Some(until);
+        self.reason = Some(reason);
+        until
+    }
+
+    pub fn succeed(&mut self) -> bool {
+        let was_failing = self.failures > 0;
+        *self = Self::default();
+        was_failing
+    }
+}
+
+/// Экспоненциальная задержка с множителем `jitter`; `Retry-After` сервера важнее.
+fn next_delay(failures: u32, retry_after: Option<Duration>, jitter: f64) -> Duration {
+    if let Some(retry_after) = retry_after {
+        return retry_after.min(MAX_DELAY);
+    }
+    let exp = BASE_DELAY.saturating_mul(1 << failures.saturating_sub(1).min(10));
+    exp.min(MAX_DELAY).mul_f64(jitter)
+}
+
+/// `Retry-After` бывает числом секунд или HTTP-датой.
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc);
+    Some((at - now).to_std().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_exponentially_and_honours_retry_after() {
+        assert_eq!(next_delay(1, None, 1.0), Duration::from_secs(15));
+        assert_eq!(next_delay(3, None, 1.0), Duration::from_secs(60));
+        assert_eq!(next_delay(30, None, 1.0), MAX_DELAY);
+        assert_eq!(next_delay(5, Some(Duration::from_secs(7)), 1.0), Duration::from_secs(7));
+
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:29:00 GMT", now),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+}
@@ -0,0 +1,37 @@
+//! Фейковый Gmail для работы без сети:
+//! `cargo run --bin fake-gmail -- fixtures/fake-gmail 127.0.0.1:8089`
+
+// Часть методов сервера нужна только тестам приложения
+#[allow(dead_code)]
+#[path = "../fake_gmail.rs"]
+mod fake_gmail;
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("fake_gmail=debug")),
+        )
+        .init();
+
+    let mut args = std::env::args().skip(1);
+    let fixtures = args
+        .next()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/fake-gmail")));
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:8089".to_string()).parse()?;
+
+    let server = fake_gmail::FakeGmail::start(&fixtures, addr).await?;
+    println!("fake gmail: {} unread messages from {}", server.unread_ids().len(), fixtures.display());
+    println!("GMAIL_TRAY_API_URL={}", server.api_url());
+    println!("GMAIL_TRAY_AUTH_URL={}/o/oauth2/v2/auth", server.base_url());
+    println!("GMAIL_TRAY_TOKEN_URL={}/token", server.base_url());
+    // Работаем до Ctrl+C
+    std::future::pending::<()>().await;
+    Ok(())
+}
@@ -7,6 +7,121 @@ use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use tauri::AppHandle;
 
+use crate::rules::{self, Rule};
+use crate::schedule::WorkSchedule;
+use crate::templates::{self, ReplyTemplate};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MailBackend {
+    #[default]
+    Gmail,
+    Imap,
+}
+
+/// Откуда берётся время письма. `internalDate` ставит сервер при получении,
+/// заголовок `Date` пишет отправитель: его бывает нет, он подделан или не разбирается.
+/// Если выбранного источника нет, используется второй.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceivedAtSource {
+    #[default]
+    InternalDate,
+    DateHeader,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImapSecurity {
+    #[default]
+    Tls,
+    StartTls,
+    Plain,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImapSettings {
+    pub host: String,
+    pub port: u16,
+    pub security: ImapSecurity,
+    pub username: String,
+    pub mailbox: String,
+    pub use_idle: bool,
+    /// Веб-интерфейс почты для кнопки «Перейти», если он есть.
+    pub web_url: Option<String>,
+}
+
+impl Default for ImapSettings {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            port: 993,
+            security: ImapSecurity::Tls,
+            username: String::new(),
+            mailbox: "INBOX".to_string(),
+            use_idle: true,
+            web_url: None,
+        }
+    }
+}
+
+/// Адрес из заголовка вида `Имя <user@example.com>`, в нижнем регистре.
+fn sender_address(sender: &str) -> String {
+    let address = match (sender.rfind('<'), sender.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &sender[start + 1..end],
+        _ => sender,
+    };
+    address.trim().to_lowercase()
+}
+
+pub const DEFAULT_GMAIL_API_URL: &str = "https://gmail.googleapis.com/gmail/v1/users/me";
+pub const DEFAULT_OAUTH_AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+pub const DEFAULT_OAUTH_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+
+/// Адреса Google API. Переопределяются здесь или переменными окружения
+/// `GMAIL_TRAY_API_URL`, `GMAIL_TRAY_AUTH_URL`, `GMAIL_TRAY_TOKEN_URL` (окружение важнее),
+/// например чтобы работать с локальным фейковым сервером.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiEndpoints {
+    pub gmail_api_url: Option<String>,
+    pub oauth_auth_url: Option<String>,
+    pub oauth_token_url: Option<String>,
+}
+
+impl ApiEndpoints {
+    pub fn gmail_api_url(&self) -> String {
+        resolve_endpoint("GMAIL_TRAY_API_URL", &self.gmail_api_url, DEFAULT_GMAIL_API_URL)
+    }
+
+    pub fn oauth_auth_url(&self) -> String {
+        resolve_endpoint("GMAIL_TRAY_AUTH_URL", &self.oauth_auth_url, DEFAULT_OAUTH_AUTH_URL)
+    }
+
+    pub fn oauth_token_url(&self) -> String {
+        resolve_endpoint("GMAIL_TRAY_TOKEN_URL", &self.oauth_token_url, DEFAULT_OAUTH_TOKEN_URL)
+    }
+}
+
+fn resolve_endpoint(env: &str, configured: &Option<String>, default: &str) -> String {
+    std::env::var(env)
+        .ok()
+        .or_else(|| configured.clone())
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| default.to_string())
+}
+
+/// Подключённый Gmail-аккаунт со своим запросом и мелодией.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountSettings {
+    pub email: String,
+    pub gmail_query: String,
+    #[serde(default)]
+    pub sound_path: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Settings {
@@ -15,6 +130,7 @@ pub struct Settings {
     pub sound_path: Option<String>,
     pub auto_launch: bool,
     pub gmail_query: String,
+    pub max_messages_per_poll: u32,
     pub oauth_client_id: String,
     pub oauth_client_secret: Option<String>,
     pub playback_volume: f32,
@@ -24,6 +140,49 @@ pub struct Settings {
     pub notification_opacity: f32,
     pub notification_expanded_width: u32,
     pub notification_expanded_height: u32,
+    pub mail_backend: MailBackend,
+    pub imap: ImapSettings,
+    pub accounts: Vec<AccountSettings>,
+    pub endpoints: ApiEndpoints,
+    pub received_at_source: ReceivedAtSource,
+    /// Быстрый ответ из уведомления; включение требует повторного входа (scope `gmail.send`).
+    pub quick_reply_enabled: bool,
+    pub reply_templates: Vec<ReplyTemplate>,
+    /// Правила для новых писем, проверяются по порядку.
+    pub rules: Vec<Rule>,
+    /// Адреса и домены, письма от которых показываются и во время snooze/тихих часов.
+    pub vip_senders: Vec<String>,
+    /// Отдельная мелодия для VIP; пусто — обычная.
+    pub vip_sound_path: Option<String>,
+    /// Рабочие часы: вне их уведомления без звука или в дайджесте.
+    pub schedule: WorkSchedule,
+    /// Отправители, для которых разрешена загрузка внешних изображений.
+    pub image_senders: Vec<String>,
+}
+
+impl Settings {
+    pub fn images_allowed_for(&self, sender: &str) -> bool {
+        self.image_senders.contains(&sender_address(sender))
+    }
+
+    /// Запись VIP-списка с `@` посередине — адрес целиком, иначе домен вместе с поддоменами.
+    pub fn is_vip(&self, email: &str) -> bool {
+        let email = email.trim().to_lowercase();
+        let Some((_, domain)) = email.rsplit_once('@') else { return false };
+        self.vip_senders.iter().any(|entry| match entry.trim_start_matches('@') {
+            entry if entry.contains('@') => *entry == email,
+            entry => domain == entry || domain.ends_with(&format!(".{entry}")),
+        })
+    }
+
+    /// Запрос Gmail для аккаунта; общий `gmail_query` — запасной вариант.
+    pub fn query_for(&self, email: &str) -> String {
+        self.accounts
+            .iter()
+            .find(|account| account.email == email)
+            .map(|account| account.gmail_query.clone())
+            .unwrap_or_else(|| self.gmail_query.clone())
+    }
 }
 
 impl Default for Settings {
@@ -34,6 +193,7 @@ impl Default for Settings {
             sound_path: None,
             auto_launch: true,
             gmail_query: "in:inbox is:unread".to_string(),
+            max_messages_per_poll: 50,
             oauth_client_id: String::new(),
             oauth_client_secret: None,
             playback_volume: 0.7,
@@ -43,6 +203,18 @@ impl Default for Settings {
             notification_opacity: 0.95,
             notification_expanded_width: 800,
             notification_expanded_height: 600,
+            mail_backend: MailBackend::Gmail,
+            imap: ImapSettings::default(),
+            accounts: Vec::new(),
+            endpoints: ApiEndpoints::default(),
+            received_at_source: ReceivedAtSource::default(),
+            quick_reply_enabled: false,
+            reply_templates: templates::default_templates(),
+            rules: Vec::new(),
+            vip_senders: Vec::new(),
+            vip_sound_path: None,
+            schedule: WorkSchedule::default(),
+            image_senders: Vec::new(),
         }
     }
 }
@@ -54,6 +226,7 @@ pub struct SettingsUpdate {
     pub sound_path: Option<Option<String>>,
     pub auto_launch: Option<bool>,
     pub gmail_query: Option<String>,
+    pub max_messages_per_poll: Option<u32>,
     pub oauth_client_id: Option<String>,
     pub oauth_client_secret: Option<Option<String>>,
     pub playback_volume: Option<f32>,
@@ -63,6 +236,17 @@ pub struct SettingsUpdate {
     pub notification_opacity: Option<f32>,
     pub notification_expanded_width: Option<u32>,
     pub notification_expanded_height: Option<u32>,
+    pub mail_backend: Option<MailBackend>,
+    pub imap: Option<ImapSettings>,
+    pub accounts: Option<Vec<AccountSettings>>,
+    pub endpoints: Option<ApiEndpoints>,
+    pub received_at_source: Option<ReceivedAtSource>,
+    pub quick_reply_enabled: Option<bool>,
+    pub reply_templates: Option<Vec<ReplyTemplate>>,
+    pub rules: Option<Vec<Rule>>,
+    pub vip_senders: Option<Vec<String>>,
+    pub vip_sound_path: Option<Option<String>>,
+    pub schedule: Option<WorkSchedule>,
 }
 
 pub struct SettingsManager {
@@ -85,6 +269,13 @@ impl SettingsManager {
     }
 
     pub fn update(&self, update: SettingsUpdate) -> Result<Settings> {
+        // Проверяем до изменений, чтобы ошибка не оставила настройки наполовину обновлёнными
+        if let Some(value) = &update.rules {
+            rules::validate(value)?;
+        }
+        if let Some(value) = &update.schedule {
+            value.validate()?;
+        }
         let mut guard = self.state.write();
         if let Some(value) = update.poll_interval_secs {
             guard.poll_interval_secs = value.clamp(15, 300);
@@ -101,6 +292,9 @@ impl SettingsManager {
         if let Some(value) = update.gmail_query {
             guard.gmail_query = value;
         }
+        if let Some(value) = update.max_messages_per_poll {
+            guard.max_messages_per_poll = value.clamp(1, 500);
+        }
         if let Some(value) = update.oauth_client_id {
             guard.oauth_client_id = value;
         }
@@ -128,6 +322,83 @@ impl SettingsManager {
         if let Some(value) = update.notification_expanded_height {
             guard.notification_expanded_height = value.clamp(300, 1080);
         }
+        if let Some(value) = update.mail_backend {
+            guard.mail_backend = value;
+        }
+        if let Some(value) = update.imap {
+            guard.imap = value;
+        }
+        if let Some(value) = update.endpoints {
+            guard.endpoints = value;
+        }
+        if let Some(value) = update.received_at_source {
+            guard.received_at_source = value;
+        }
+        if let Some(value) = update.quick_reply_enabled {
+            guard.quick_reply_enabled = value;
+        }
+        if let Some(value) = update.reply_templates {
+            guard.reply_templates = templates::normalise(value);
+        }
+        if let Some(value) = update.rules {
+            guard.rules = value;
+        }
+        if let Some(value) = update.vip_senders {
+            let mut senders: Vec<String> = Vec::with_capacity(value.len());
+            for sender in value.iter().map(|s| s.trim().to_lowercase()).filter(|s| !s.is_empty()) {
+                if !senders.contains(&sender) {
+                    senders.push(sender);
+                }
+            }
+            guard.vip_senders = senders;
+        }
+        if let Some(value) = update.vip_sound_path {
+            guard.vip_sound_path = value;
+        }
+        if let Some(value) = update.schedule {
+            guard.schedule = value;
+        }
+        if let Some(value) = update.accounts {
+            // Список аккаунтов меняется только входом/выходом, здесь правим их параметры
+            for account in guard.accounts.iter_mut() {
+                if let Some(updated) = value.iter().find(|a| a.email == account.email) {
+                    account.gmail_query = updated.gmail_query.clone();
+                    account.sound_path = updated.sound_path.clone();
+                }
+            }
+        }
+        save_settings(&self.path, &guard)?;
+        Ok(guard.clone())
+    }
+
+    pub fn add_account(&self, email: &str) -> Result<Settings> {
+        let mut guard = self.state.write();
+        if !guard.accounts.iter().any(|account| account.email == email) {
+            let gmail_query = guard.gmail_query.clone();
+            guard.accounts.push(AccountSettings {
+                email: email.to_string(),
+                gmail_query,
+                sound_path: None,
+            });
+            save_settings(&self.path, &guard)?;
+        }
+        Ok(guard.clone())
+    }
+
+    pub fn set_sender_images(&self, sender: &str, allowed: bool) -> Result<Settings> {
+        let address = sender_address(sender);
+        let mut guard = self.state.write();
+        guard.image_senders.retain(|existing| *existing != address);
+        if allowed && !address.is_empty() {
+            guard.image_senders.push(address);
+        }
+        save_settings(&self.path, &guard)?;
+        Ok(guard.clone())
+    }
+
+    pub fn remove_account(&self, email: &str) -> Result<Settings> {
+        let mut guard = self.state.write();
+        guard.accounts.retain(|account| account.email != email);
         save_settings(&self.path, &guard)?;
         Ok(guard.clone())
     }
@@ -137,10 +408,14 @@ impl SettingsManager {
     }
 }
 
-fn ensure_settings_path(_app: &AppHandle) -> Result<PathBuf> {
+pub fn project_dirs() -> Result<ProjectDirs> {
     // Use directories crate to determine a config directory compatible with Tauri v2
-    let proj = ProjectDirs::from("org", "kreditpro", "GmailTrayNotifier")
-        .context("Unable to resolve configuration directory")?;
+    ProjectDirs::from("org", "kreditpro", "GmailTrayNotifier")
+        .context("Unable to resolve configuration directory")
+}
+
+fn ensure_settings_path(_app: &AppHandle) -> Result<PathBuf> {
+    let proj = project_dirs()?;
     let mut dir = proj.config_dir().to_path_buf();
     if !dir.exists() {
         fs::create_dir_all(&dir).context("Failed to create configuration directory")?;
@@ -168,3 +443,22 @@ fn save_settings(path: &Path, settings: &Settings) -> Result<()> {
     fs::write(path, json).context("Failed to write settings file")?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_vip_addresses_and_domains() {
+        let settings = Settings {
+            vip_senders: vec!["boss@example.com".into(), "@corp.example".into(), "bank.ru".into()],
+            ..Settings::default()
+        };
+        assert!(settings.is_vip("Boss@Example.com"));
+        assert!(!settings.is_vip("other@example.com"));
+        assert!(settings.is_vip("ci@build.corp.example"));
+        assert!(settings.is_vip("noreply@bank.ru"));
+        assert!(!settings.is_vip("noreply@notbank.ru"));
+        assert!(!settings.is_vip("bank.ru"));
+    }
+}
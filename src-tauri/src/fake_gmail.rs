@@ -0,0 +1,499 @@
+//! Локальный фейковый Gmail для разработки без сети и для интеграционных тестов.
+//! Отдаёт `messages.list`/`get`/`modify`/`trash`/`send`, `messages.attachments.get`, `threads.modify`/`trash`,
+//! `labels.list`, `profile`, `history` и OAuth-эндпоинты из фикстур; поисковый запрос `q` не разбирается, список — все письма
+//! с `UNREAD`. Маска `fields` у `messages.get` применяется, как partial response у Gmail (только `a,b(c,d)`, без `/`).
+//! Вложения лежат файлами в `attachments/`. В историю попадают только письма,
+//! доставленные тестом через [`FakeGmail::deliver`].
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+pub const API_PREFIX: &str = "/gmail/v1/users/me";
+pub const FAKE_ACCESS_TOKEN: &str = "fake-access-token";
+
+struct Mailbox {
+    attachments: PathBuf,
+    labels: Value,
+    email: String,
+    history_id: u64,
+    messages: BTreeMap<String, Value>,
+    /// Отправленные через `messages.send`, в списки непрочитанных не попадают
+    sent: Vec<Value>,
+    /// Письма, на `get` которых сервер отвечает 503
+    failing: HashSet<String>,
+    /// Записи `history.list` с полем `id`
+    history: Vec<Value>,
+    list_calls: usize,
+}
+
+pub struct FakeGmail {
+    addr: SocketAddr,
+    mailbox: Arc<Mutex<Mailbox>>,
+}
+
+impl FakeGmail {
+    /// Загружает `profile.json` и `messages/*.json` из каталога фикстур и начинает слушать `addr`.
+    pub async fn start(fixtures: &Path, addr: SocketAddr) -> Result<Self> {
+        let profile: Value = read_json(&fixtures.join("profile.json"))?;
+        let mut messages = BTreeMap::new();
+        let dir = fixtures.join("messages");
+        for entry in fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let message = read_json(&path)?;
+            let id = message["id"]
+                .as_str()
+                .with_context(|| format!("{} has no id", path.display()))?
+                .to_string();
+            messages.insert(id, message);
+        }
+        let mailbox = Arc::new(Mutex::new(Mailbox {
+            attachments: fixtures.join("attachments"),
+            labels: read_json(&fixtures.join("labels.json")).unwrap_or_else(|_| json!({ "labels": [] })),
+            email: profile["emailAddress"].as_str().unwrap_or("fake@example.com").to_string(),
+            history_id: profile["historyId"].as_str().and_then(|id| id.parse().ok()).unwrap_or(1),
+            messages,
+            sent: Vec::new(),
+            failing: HashSet::new(),
+            history: Vec::new(),
+            list_calls: 0,
+        }));
+
+        let listener = TcpListener::bind(addr).await.context("Failed to bind fake gmail")?;
+        let addr = listener.local_addr()?;
+        let server_mailbox = mailbox.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else { continue };
+                let mailbox = server_mailbox.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = serve(stream, addr, mailbox).await {
+                        warn!(%err, "fake gmail: failed to serve request");
+                    }
+                });
+            }
+        });
+        Ok(Self { addr, mailbox })
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn api_url(&self) -> String {
+        format!("{}{API_PREFIX}", self.base_url())
+    }
+
+    pub fn labels_of(&self, id: &str) -> Vec<String> {
+        self.mailbox.lock().messages[id]["labelIds"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|label| label.as_str().map(str::to_string))
+            .collect()
+    }
+
+    /// Отправленные письма: `threadId` и сырой RFC 822 из поля `raw`.
+    pub fn sent(&self) -> Vec<(Option<String>, String)> {
+        self.mailbox
+            .lock()
+            .sent
+            .iter()
+            .map(|message| {
+                let raw = message["raw"].as_str().unwrap_or_default();
+                let raw = URL_SAFE.decode(raw).ok().and_then(|bytes| String::from_utf8(bytes).ok());
+                (message["threadId"].as_str().map(str::to_string), raw.unwrap_or_default())
+            })
+            .collect()
+    }
+
+    /// Включает или выключает ответ 503 на `messages.get` для письма.
+    pub fn fail_message(&self, id: &str, fail: bool) {
+        let mut mailbox = self.mailbox.lock();
+        if fail {
+            mailbox.failing.insert(id.to_string());
+        } else {
+            mailbox.failing.remove(id);
+        }
+    }
+
+    /// Кладёт письмо в ящик и записывает `messagesAdded` в историю.
+    pub fn deliver(&self, message: Value) {
+        let mut mailbox = self.mailbox.lock();
+        let id = message["id"].as_str().expect("message has id").to_string();
+        mailbox.history_id += 1;
+        let record = json!({
+            "id": mailbox.history_id.to_string(),
+            "messagesAdded": [{
+                "message": { "id": id, "threadId": message["threadId"], "labelIds": message["labelIds"] }
+            }],
+        });
+        mailbox.history.push(record);
+        mailbox.messages.insert(id, message);
+    }
+
+    /// Сколько раз запрашивали `messages.list`.
+    pub fn list_calls(&self) -> usize {
+        self.mailbox.lock().list_calls
+    }
+
+    pub fn unread_ids(&self) -> Vec<String> {
+        self.mailbox
+            .lock()
+            .messages
+            .iter()
+            .filter(|(_, message)| has_label(message, "UNREAD"))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+}
+
+fn read_json(path: &Path) -> Result<Value> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&bytes).with_context(|| format!("Invalid fixture {}", path.display()))
+}
+
+fn has_label(message: &Value, label: &str) -> bool {
+    message["labelIds"]
+        .as_array()
+        .is_some_and(|labels| labels.iter().any(|l| l == label))
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target.as_str(), ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |v: &str| {
+                let v = v.replace('+', " ");
+                urlencoding::decode(&v).map(|d| d.into_owned()).unwrap_or(v)
+            };
+            (decode(key), decode(value))
+        })
+        .collect();
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        query,
+        body,
+    })
+}
+
+async fn serve(mut stream: TcpStream, addr: SocketAddr, mailbox: Arc<Mutex<Mailbox>>) -> Result<()> {
+    let request = read_request(&mut stream).await?;
+    debug!(method = %request.method, path = %request.path, "fake gmail: request");
+    let response = route(&request, addr, &mut mailbox.lock());
+    let (status, headers, body) = match response {
+        Response::Json(status, value) => (status, "content-type: application/json\r\n".to_string(), value.to_string()),
+        Response::Redirect(location) => ("302 Found", format!("location: {location}\r\n"), String::new()),
+    };
+    let raw = format!(
+        "HTTP/1.1 {status}\r\n{headers}content-length: {len}\r\nconnection: close\r\n\r\n{body}",
+        len = body.len()
+    );
+    stream.write_all(raw.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+enum Response {
+    Json(&'static str, Value),
+    Redirect(String),
+}
+
+fn not_found() -> Response {
+    Response::Json("404 Not Found", json!({ "error": { "code": 404, "message": "Not Found" } }))
+}
+
+fn route(request: &Request, addr: SocketAddr, mailbox: &mut Mailbox) -> Response {
+    // OAuth: авторизация сразу возвращает код на redirect_uri, токен выдаётся любому коду
+    if request.path == "/o/oauth2/v2/auth" {
+        let redirect = request.param("redirect_uri").unwrap_or_default();
+        let state = urlencoding::encode(request.param("state").unwrap_or_default()).into_owned();
+        return Response::Redirect(format!("{redirect}?code=fake-code&state={state}"));
+    }
+    if request.path == "/token" {
+        return Response::Json(
+            "200 OK",
+            json!({
+                "access_token": FAKE_ACCESS_TOKEN,
+                "token_type": "Bearer",
+                "expires_in": 3600,
+                "refresh_token": "fake-refresh-token",
+            }),
+        );
+    }
+
+    let Some(path) = request.path.strip_prefix(API_PREFIX) else {
+        return not_found();
+    };
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["profile"]) => Response::Json(
+            "200 OK",
+            json!({ "emailAddress": mailbox.email, "historyId": mailbox.history_id.to_string() }),
+        ),
+        ("GET", ["history"]) => {
+            let start: u64 = request.param("startHistoryId").and_then(|id| id.parse().ok()).unwrap_or(0);
+            let records: Vec<&Value> = mailbox
+                .history
+                .iter()
+                .filter(|record| record["id"].as_str().and_then(|id| id.parse::<u64>().ok()) > Some(start))
+                .collect();
+            Response::Json(
+                "200 OK",
+                json!({ "history": records, "historyId": mailbox.history_id.to_string() }),
+            )
+        }
+        ("GET", ["messages"]) => {
+            mailbox.list_calls += 1;
+            let unread: Vec<&String> = mailbox
+                .messages
+                .iter()
+                .filter(|(_, message)| has_label(message, "UNREAD"))
+                .map(|(id, _)| id)
+                .collect();
+            let offset: usize = request.param("pageToken").and_then(|t| t.parse().ok()).unwrap_or(0);
+            let size: usize = request.param("maxResults").and_then(|m| m.parse().ok()).unwrap_or(100);
+            let page: Vec<Value> = unread
+                .iter()
+                .skip(offset)
+                .take(size)
+                .map(|id| json!({ "id": id, "threadId": mailbox.messages[*id]["threadId"] }))
+                .collect();
+            let mut body = json!({ "messages": page, "resultSizeEstimate": unread.len() });
+            if offset + size < unread.len() {
+                body["nextPageToken"] = json!((offset + size).to_string());
+            }
+            Response::Json("200 OK", body)
+        }
+        ("GET", ["messages", id]) if mailbox.failing.contains(*id) => Response::Json(
+            "503 Service Unavailable",
+            json!({ "error": { "code": 503, "message": "The service is currently unavailable." } }),
+        ),
+        ("GET", ["messages", id]) => {
+            let message = match mailbox.messages.get(*id) {
+                Some(message) if request.param("format") == Some("metadata") => metadata_only(message, request),
+                Some(message) => message.clone(),
+                None => return not_found(),
+            };
+            match request.param("fields") {
+                Some(mask) => Response::Json("200 OK", select_fields(&message, &parse_fields(mask))),
+                None => Response::Json("200 OK", message),
+            }
+        }
+        ("GET", ["messages", id, "attachments", attachment_id]) => {
+            if !mailbox.messages.contains_key(*id) || attachment_id.contains("..") {
+                return not_found();
+            }
+            match fs::read(mailbox.attachments.join(attachment_id)) {
+                Ok(bytes) => Response::Json(
+                    "200 OK",
+                    json!({ "size": bytes.len(), "data": URL_SAFE.encode(&bytes) }),
+                ),
+                Err(_) => not_found(),
+            }
+        }
+        ("POST", ["messages", "send"]) => {
+            let message: Value = serde_json::from_slice(&request.body).unwrap_or_default();
+            if !message["raw"].is_string() {
+                return Response::Json(
+                    "400 Bad Request",
+                    json!({ "error": { "code": 400, "message": "'raw' RFC822 payload message string required" } }),
+                );
+            }
+            let id = format!("sent-{:03}", mailbox.sent.len() + 1);
+            let thread_id = message["threadId"].as_str().unwrap_or(&id).to_string();
+            let sent = json!({ "id": id, "threadId": thread_id, "labelIds": ["SENT"], "raw": message["raw"] });
+            mailbox.sent.push(sent);
+            mailbox.history_id += 1;
+            Response::Json("200 OK", json!({ "id": id, "threadId": thread_id, "labelIds": ["SENT"] }))
+        }
+        ("GET", ["labels"]) => Response::Json("200 OK", mailbox.labels.clone()),
+        // Корзина в Gmail — это метка TRASH вместо INBOX
+        ("POST", [resource @ ("messages" | "threads"), id, "trash"]) => {
+            let change = json!({ "addLabelIds": ["TRASH"], "removeLabelIds": ["INBOX"] });
+            apply_change(mailbox, resource, id, &change)
+        }
+        ("POST", [resource @ ("messages" | "threads"), id, "modify"]) => {
+            let change: Value = serde_json::from_slice(&request.body).unwrap_or_default();
+            apply_change(mailbox, resource, id, &change)
+        }
+        _ => {
+            debug!(%addr, path = %request.path, "fake gmail: unknown endpoint");
+            not_found()
+        }
+    }
+}
+
+/// Меняет метки письма или всех писем цепочки.
+fn apply_change(mailbox: &mut Mailbox, resource: &str, id: &str, change: &Value) -> Response {
+    if resource == "messages" {
+        let Some(message) = mailbox.messages.get_mut(id) else {
+            return not_found();
+        };
+        modify_labels(message, change);
+        let message = message.clone();
+        mailbox.history_id += 1;
+        return Response::Json("200 OK", message);
+    }
+    let mut thread: Vec<Value> = Vec::new();
+    for message in mailbox.messages.values_mut().filter(|m| m["threadId"] == id) {
+        modify_labels(message, change);
+        thread.push(json!({ "id": message["id"], "labelIds": message["labelIds"] }));
+    }
+    if thread.is_empty() {
+        return not_found();
+    }
+    mailbox.history_id += 1;
+    Response::Json("200 OK", json!({ "id": id, "messages": thread }))
+}
+
+fn modify_labels(message: &mut Value, change: &Value) {
+    let mut labels: Vec<Value> = message["labelIds"].as_array().cloned().unwrap_or_default();
+    if let Some(remove) = change["removeLabelIds"].as_array() {
+        labels.retain(|label| !remove.contains(label));
+    }
+    for label in change["addLabelIds"].as_array().into_iter().flatten() {
+        if !labels.contains(label) {
+            labels.push(label.clone());
+        }
+    }
+    message["labelIds"] = Value::Array(labels);
+}
+
+/// Как `format=metadata` у Gmail: без тела и частей, только запрошенные заголовки.
+fn metadata_only(message: &Value, request: &Request) -> Value {
+    let wanted: Vec<&str> = request
+        .query
+        .iter()
+        .filter(|(key, _)| key == "metadataHeaders")
+        .map(|(_, value)| value.as_str())
+        .collect();
+    let headers: Vec<Value> = message["payload"]["headers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|header| {
+            let name = header["name"].as_str().unwrap_or_default();
+            wanted.is_empty() || wanted.iter().any(|w| w.eq_ignore_ascii_case(name))
+        })
+        .cloned()
+        .collect();
+    let mut metadata = message.clone();
+    metadata["payload"] = json!({
+        "mimeType": message["payload"]["mimeType"],
+        "headers": headers,
+    });
+    metadata
+}
+
+/// Поле маски `fields`; пустой `nested` — поле целиком.
+struct Field {
+    name: String,
+    nested: Vec<Field>,
+}
+
+fn parse_fields(mask: &str) -> Vec<Field> {
+    fn list(chars: &mut std::str::Chars) -> Vec<Field> {
+        let mut fields = Vec::new();
+        let mut name = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '(' => {
+                    let nested = list(chars);
+                    fields.push(Field { name: std::mem::take(&mut name), nested });
+                }
+                ',' | ')' => {
+                    if !name.is_empty() {
+                        fields.push(Field { name: std::mem::take(&mut name), nested: Vec::new() });
+                    }
+                    if c == ')' {
+                        return fields;
+                    }
+                }
+                c if !c.is_whitespace() => name.push(c),
+                _ => {}
+            }
+        }
+        if !name.is_empty() {
+            fields.push(Field { name, nested: Vec::new() });
+        }
+        fields
+    }
+    list(&mut mask.chars())
+}
+
+/// Оставляет в ответе только поля из маски; к массивам маска применяется поэлементно.
+fn select_fields(value: &Value, fields: &[Field]) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.iter().map(|item| select_fields(item, fields)).collect()),
+        Value::Object(object) => Value::Object(
+            fields
+                .iter()
+                .filter_map(|field| {
+                    let value = object.get(&field.name)?;
+                    let value = match field.nested.as_slice() {
+                        [] => value.clone(),
+                        nested => select_fields(value, nested),
+                    };
+                    Some((field.name.clone(), value))
+                })
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
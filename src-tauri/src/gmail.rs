@@ -1,202 +1,1055 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-use anyhow::{bail, Context, Result};
-use base64::{Engine as _, engine::general_purpose::STANDARD as base64};
-use chrono::{DateTime, Utc};
-use lru::LruCache;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use base64::{Engine as _, engine::general_purpose::{STANDARD as base64, URL_SAFE}};
+use chrono::{DateTime, Datelike, Local, TimeZone, Utc};
+use encoding_rs::{Encoding, UTF_8};
+use futures::stream::{self, StreamExt};
+use once_cell::sync::Lazy;
 use parking_lot::Mutex;
-use reqwest::StatusCode;
+use reqwest::header::RETRY_AFTER;
+use regex::Regex;
+use reqwest::{RequestBuilder, Response, StatusCode};
 use serde::Deserialize;
 use serde::Serialize;
 use tokio::time::timeout;
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 
+use crate::backoff::{parse_retry_after, Backoff};
+use crate::config::ReceivedAtSource;
+use crate::headers::{decode_header, encode_header, join_display, parse_address_list, EmailAddress};
 use crate::oauth::{AccessTokenProvider, OAuthError};
+use crate::provider::{MailBody, MailLabel, MailProvider, MessageAction};
+use crate::seen::{NotificationOutcome, SeenStore};
 
-const GMAIL_API: &str = "https://gmail.googleapis.com/gmail/v1/users/me";
+const LIST_PAGE_SIZE: usize = 100;
+/// Таймаут запросов к Gmail API, в том числе из входа в аккаунт.
+pub(crate) const HTTP_TIMEOUT: Duration = Duration::from_secs(20);
+// Сколько писем сверх лимита просматриваем при полной синхронизации, чтобы посчитать в сводке только новые
+const OVERFLOW_SCAN_LIMIT: usize = 500;
+// Сколько писем запрашиваем параллельно при опросе
+const FETCH_CONCURRENCY: usize = 8;
+// Для тоста нужны заголовки и структура частей (вложения), но не их содержимое.
+// Partial response не умеет рекурсию, поэтому вложенность частей ограничена четырьмя уровнями.
+const SUMMARY_FIELDS: &str = "id,threadId,labelIds,snippet,internalDate,payload(mimeType,headers,\
+parts(partId,mimeType,filename,body(size,attachmentId),\
+parts(partId,mimeType,filename,body(size,attachmentId),\
+parts(partId,mimeType,filename,body(size,attachmentId),\
+parts(partId,mimeType,filename,body(size,attachmentId))))))";
+const BODY_CACHE_SIZE: usize = 32;
+// Сколько байт встроенных (cid:) картинок превращаем в data: URI на одно письмо
+const INLINE_IMAGES_LIMIT: usize = 2 * 1024 * 1024;
+const SUMMARY_ID_PREFIX: &str = "summary:";
+/// Заголовки исходного письма, нужные для ответа.
+const REPLY_HEADERS: [&str; 5] = ["Subject", "From", "Reply-To", "Message-ID", "References"];
+
+/// Ошибки Gmail API после классификации: временные пережидаем, фатальные показываем как есть.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum GmailApiError {
+    #[error("gmail is temporarily unavailable ({reason}), retry at {retry_at}")]
+    Transient {
+        reason: String,
+        retry_at: DateTime<Utc>,
+    },
+    #[error("gmail {what} returned status {status} body={body}")]
+    Fatal {
+        what: &'static str,
+        status: StatusCode,
+        body: String,
+    },
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GmailNotification {
     pub id: String,
+    pub account: String,
     pub thread_id: String,
     pub subject: String,
     pub snippet: Option<String>,
+    /// `From` и `To` одной строкой для показа.
     pub sender: Option<String>,
     pub recipient: Option<String>,
+    #[serde(default)]
+    pub from: Option<EmailAddress>,
+    #[serde(default)]
+    pub to: Vec<EmailAddress>,
+    #[serde(default)]
+    pub cc: Vec<EmailAddress>,
+    #[serde(default)]
+    pub reply_to: Vec<EmailAddress>,
+    /// Время письма по `received_at_source` из настроек.
     pub received_at: Option<DateTime<Utc>>,
+    /// Заголовок `Date` и время получения сервером (`internalDate`/INTERNALDATE).
+    #[serde(default)]
+    pub header_date: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub internal_date: Option<DateTime<Utc>>,
+    /// Локальное время и «5 мин назад», заполняются перед показом.
+    #[serde(default)]
+    pub received_local: Option<String>,
+    #[serde(default)]
+    pub received_relative: Option<String>,
     pub url: String,
-    pub body: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// Более ранние письма той же цепочки, слитые в это уведомление.
+    #[serde(default)]
+    pub merged_ids: Vec<String>,
+    /// Идентификаторы меток Gmail, для правил.
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Мелодия из сработавшего правила вместо настроенной.
+    #[serde(default)]
+    pub sound_path: Option<String>,
+    /// Правило подняло приоритет: в очереди встаёт перед обычными.
+    #[serde(default)]
+    pub priority: bool,
+    /// Отправитель из VIP-списка: показывается и во время snooze, со своим стилем и звуком.
+    #[serde(default)]
+    pub vip: bool,
+    /// Нерабочее время по расписанию: показать без звука.
+    #[serde(default)]
+    pub silent: bool,
+    /// Тело не передаётся с уведомлением, его отдаёт `get_message_body`.
+    #[serde(default)]
+    pub has_body: bool,
+    /// Архив, корзина, важность и метки доступны; иначе из действий есть только звёздочка.
+    #[serde(default)]
+    pub mailbox_actions: bool,
+    /// Сводка «и ещё N писем» или дайджест: за уведомлением нет одного письма, действий над ним нет.
+    #[serde(default)]
+    pub summary: bool,
+}
+
+impl GmailNotification {
+    /// Письма одной цепочки показываются одним уведомлением.
+    pub fn same_thread(&self, other: &GmailNotification) -> bool {
+        !self.summary
+            && !other.summary
+            && !self.thread_id.is_empty()
+            && self.account == other.account
+            && self.thread_id == other.thread_id
+    }
+
+    /// Сливает письмо той же цепочки: показываем самое новое, id остальных запоминаем.
+    pub fn absorb(&mut self, mut other: GmailNotification) {
+        let mut merged = std::mem::take(&mut self.merged_ids);
+        merged.append(&mut other.merged_ids);
+        // Решения правил относятся ко всей цепочке
+        let priority = self.priority || other.priority;
+        let vip = self.vip || other.vip;
+        let sound_path = other.sound_path.take().or_else(|| self.sound_path.take());
+        if other.received_at >= self.received_at {
+            merged.push(std::mem::replace(self, other).id);
+        } else {
+            merged.push(other.id);
+        }
+        self.merged_ids = merged;
+        self.priority = priority;
+        self.vip = vip;
+        self.sound_path = sound_path;
+    }
+
+    /// Все письма уведомления: слитые и показанное.
+    pub fn message_ids(&self) -> Vec<String> {
+        let mut ids = self.merged_ids.clone();
+        ids.push(self.id.clone());
+        ids
+    }
+
+    pub fn apply_received_at(&mut self, source: ReceivedAtSource) {
+        self.received_at = match source {
+            ReceivedAtSource::InternalDate => self.internal_date.or(self.header_date),
+            ReceivedAtSource::DateHeader => self.header_date.or(self.internal_date),
+        };
+    }
+
+    /// Копия с временем письма в локальном часовом поясе для показа.
+    pub fn localised(mut self, now: DateTime<Utc>) -> Self {
+        if let Some(at) = self.received_at {
+            self.received_local = Some(at.with_timezone(&Local).format("%d.%m.%Y %H:%M").to_string());
+            self.received_relative = Some(relative_time(at, now, &Local));
+        }
+        self
+    }
+
+    /// Сводное уведомление «и ещё N писем», когда упёрлись в лимит опроса.
+    pub(crate) fn overflow_summary(account: &str, count: usize, url: &str) -> Self {
+        Self {
+            id: format!("{SUMMARY_ID_PREFIX}{}", uuid::Uuid::new_v4()),
+            account: account.to_string(),
+            thread_id: String::new(),
+            subject: format!("И ещё {count} непрочитанных писем"),
+            snippet: Some("Откройте почту, чтобы просмотреть остальные письма".to_string()),
+            sender: None,
+            recipient: None,
+            from: None,
+            to: Vec::new(),
+            cc: Vec::new(),
+            reply_to: Vec::new(),
+            received_at: None,
+            header_date: None,
+            internal_date: None,
+            received_local: None,
+            received_relative: None,
+            url: url.to_string(),
+            attachments: Vec::new(),
+            merged_ids: Vec::new(),
+            labels: Vec::new(),
+            sound_path: None,
+            priority: false,
+            vip: false,
+            silent: false,
+            has_body: false,
+            mailbox_actions: false,
+            summary: true,
+        }
+    }
+
+    /// Одно уведомление вместо писем, скрытых в нерабочее время.
+    pub(crate) fn digest(messages: &[GmailNotification]) -> Self {
+        const LISTED: usize = 5;
+        let first = &messages[0];
+        let url = if first.url.starts_with("https://mail.google.com/") {
+            mailbox_url(&first.account, "inbox")
+        } else {
+            first.url.clone()
+        };
+        let mut lines: Vec<String> = messages
+            .iter()
+            .take(LISTED)
+            .map(|n| {
+                let sender = n.from.as_ref().map(|from| from.name.clone().unwrap_or_else(|| from.email.clone()));
+                format!("{} — {}", sender.unwrap_or_else(|| "Без отправителя".into()), n.subject)
+            })
+            .collect();
+        if messages.len() > LISTED {
+            lines.push(format!("и ещё {}", messages.len() - LISTED));
+        }
+
+        let mut digest = Self::overflow_summary(&first.account, messages.len(), &url);
+        digest.subject = format!("За нерабочее время: {} писем", messages.len());
+        digest.snippet = Some(lines.join("\n"));
+        digest.received_at = messages.iter().filter_map(|n| n.received_at).max();
+        digest
+    }
+
+    /// Обычное письмо Gmail для тестов; остальные поля тест заполняет сам.
+    #[cfg(test)]
+    pub(crate) fn for_test(id: &str, thread: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            account: "me@example.com".to_string(),
+            thread_id: thread.to_string(),
+            subject: String::new(),
+            snippet: None,
+            sender: None,
+            recipient: None,
+            from: None,
+            to: Vec::new(),
+            cc: Vec::new(),
+            reply_to: Vec::new(),
+            received_at: None,
+            header_date: None,
+            internal_date: None,
+            received_local: None,
+            received_relative: None,
+            url: String::new(),
+            attachments: Vec::new(),
+            merged_ids: Vec::new(),
+            labels: Vec::new(),
+            sound_path: None,
+            priority: false,
+            vip: false,
+            silent: false,
+            has_body: true,
+            mailbox_actions: true,
+            summary: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub id: String,
+    pub filename: String,
+    pub mime_type: String,
+    pub size: u64,
 }
 
 #[derive(Clone)]
 pub struct GmailClient {
+    account: String,
+    api_url: String,
     http: reqwest::Client,
     token_provider: Arc<dyn AccessTokenProvider>,
-    dedup: Arc<Mutex<LruCache<String, Instant>>>,
+    seen: Arc<SeenStore>,
+    history_id: Arc<Mutex<Option<String>>>,
+    backoff: Arc<Mutex<Backoff>>,
+    bodies: Arc<Mutex<BodyCache>>,
 }
 
 impl GmailClient {
-    pub fn new(token_provider: Arc<dyn AccessTokenProvider>) -> Result<Self> {
+    pub fn new(
+        account: &str,
+        api_url: String,
+        token_provider: Arc<dyn AccessTokenProvider>,
+        seen: Arc<SeenStore>,
+    ) -> Result<Self> {
         let http = reqwest::Client::builder()
             .use_rustls_tls()
-            .timeout(Duration::from_secs(20))
+            .timeout(HTTP_TIMEOUT)
             .build()
             .context("failed to construct HTTP client")?;
         Ok(Self {
+            account: account.to_string(),
+            api_url,
             http,
             token_provider,
-            dedup: Arc::new(Mutex::new(LruCache::new(
-                std::num::NonZeroUsize::new(200).unwrap(),
-            ))),
+            seen,
+            history_id: Arc::new(Mutex::new(None)),
+            backoff: Arc::new(Mutex::new(Backoff::default())),
+            bodies: Arc::new(Mutex::new(BodyCache::default())),
         })
     }
 
-    pub async fn fetch_unread(&self, query: &str) -> Result<Vec<GmailNotification>> {
-        let token = self.token_provider.access_token().await?;
-        let url = format!("{}/messages", GMAIL_API);
-        debug!(%url, %query, "gmail: listing messages");
-        let response = self
-            .http
-            .get(url)
-            .bearer_auth(token)
-            .query(&[("q", query), ("maxResults", "10"), ("labelIds", "UNREAD")])
-            .send()
-            .await
-            .context("failed to list gmail messages")?;
+    pub async fn fetch_unread(&self, query: &str, limit: usize) -> Result<Vec<GmailNotification>> {
+        // Сначала пробуем инкрементальную синхронизацию через History API
+        let start_history_id = self.history_id.lock().clone();
+        let delta = match start_history_id {
+            Some(ref start) => {
+                let delta = self.list_history(start).await?;
+                if delta.is_none() {
+                    warn!(%start, "gmail: historyId устарел, выполняем полную синхронизацию");
+                }
+                delta
+            }
+            None => None,
+        };
 
-        debug!(status = ?response.status(), "gmail: list response");
-        if response.status() == StatusCode::UNAUTHORIZED {
-            return Err(OAuthError::NotAuthorised.into());
+        // Письма из истории проверяем по меткам сами; полный список — только без истории
+        let (sync_point, fresh, skipped, required) = match delta {
+            Some(delta) if delta.added.is_empty() => {
+                debug!(start = ?start_history_id, "gmail: история без новых писем");
+                *self.history_id.lock() = Some(delta.history_id);
+                return Ok(Vec::new());
+            }
+            Some(delta) => {
+                let (fresh, skipped, required) = self.history_candidates(query, limit, delta.added).await?;
+                (delta.history_id, fresh, skipped, required)
+            }
+            None => {
+                // Фиксируем точку отсчёта до полного списка, чтобы не потерять письма,
+                // пришедшие во время синхронизации
+                let sync_point = self.current_history_id().await?;
+                let mut page = self.list_messages(query, limit.max(1) + OVERFLOW_SCAN_LIMIT).await?;
+                debug!(count = page.items.len(), truncated = page.truncated, "gmail: list parsed");
+                // В сводку идут только ещё не показанные письма из-за лимита; дальше окна
+                // просмотра не заглядываем
+                let rest = page.items.split_off(limit.max(1).min(page.items.len()));
+                let unknown = |items: Vec<MessageRef>| -> Vec<String> {
+                    items.into_iter().map(|item| item.id).filter(|id| !self.is_known(id)).collect()
+                };
+                (sync_point, unknown(page.items), unknown(rest), None)
+            }
+        };
+
+        // Для тоста хватает заголовков; тело загрузится, когда его попросит развёрнутый вид
+        let mut results = stream::iter(fresh)
+            .map(|id| async move {
+                let result = self.fetch_metadata(&id).await;
+                (id, result)
+            })
+            .buffered(FETCH_CONCURRENCY);
+        let mut notifications = Vec::new();
+        while let Some((id, result)) = results.next().await {
+            match result {
+                Ok(notification) => notifications.push(notification),
+                // Письмо удалили между историей и загрузкой — показывать нечего
+                Err(err) if is_gone(&err) => warn!(%err, message_id = %id, "gmail: письмо уже удалено, пропускаем"),
+                // При любом другом сбое не сдвигаем точку синхронизации и ничего не отмечаем
+                // показанным: недополученные письма заберём при следующем опросе
+                Err(err) => return Err(err),
+            }
+        }
+        // Метки в истории могли устареть: письмо успели прочитать до запроса
+        if let Some(required) = required {
+            notifications.retain(|n| matches_labels(&n.labels, &required));
+        }
+        // Не влезшие в лимит уже учтены в сводке: повторная синхронизация не покажет её снова
+        self.seen
+            .mark_seen_many(notifications.iter().map(|n| &n.id).chain(&skipped));
+        debug!(history_id = %sync_point, "gmail: синхронизация завершена");
+        *self.history_id.lock() = Some(sync_point);
+        notifications.sort_by_key(|n| n.received_at);
+        let overflow = skipped.len();
+        if overflow > 0 {
+            info!(overflow, limit, "gmail: достигнут лимит писем за опрос");
+            notifications.push(GmailNotification::overflow_summary(
+                &self.account,
+                overflow,
+                &mailbox_url(&self.account, "inbox"),
+            ));
         }
+        Ok(notifications)
+    }
 
-        if response.status().is_client_error() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            bail!("gmail list returned status {status} body={body}");
+    /// Новые письма из истории, которые стоит загрузить, не влезшие в лимит
+    /// и метки, обязательные по запросу. Запрос, который не сводится к меткам
+    /// (`from:`, `subject:` и т. п.), локально не проверить — тогда пересекаем
+    /// историю со списком по запросу.
+    async fn history_candidates(
+        &self,
+        query: &str,
+        limit: usize,
+        added: Vec<(String, Vec<String>)>,
+    ) -> Result<(Vec<String>, Vec<String>, Option<Vec<String>>)> {
+        let required = query_labels(query);
+        let mut candidates: Vec<String> = added
+            .into_iter()
+            .filter(|(id, labels)| {
+                !self.is_known(id)
+                    && match &required {
+                        // Без меток в записи истории проверим их после загрузки
+                        Some(required) => labels.is_empty() || matches_labels(labels, required),
+                        None => true,
+                    }
+            })
+            .map(|(id, _)| id)
+            .collect();
+        if required.is_none() && !candidates.is_empty() {
+            let page = self.list_messages(query, limit).await?;
+            // Вышедшие за окно списка не считаем: подходят ли они под запрос, неизвестно
+            let listed: HashSet<String> = page.items.into_iter().map(|item| item.id).collect();
+            candidates.retain(|id| listed.contains(id));
         }
+        // История идёт от старых к новым: при переполнении показываем самые новые
+        let overflow = candidates.len().saturating_sub(limit.max(1));
+        let skipped = candidates.drain(..overflow).collect();
+        Ok((candidates, skipped, required))
+    }
 
-        let list: MessageList = response
-            .json()
-            .await
-            .context("invalid gmail list response")?;
-        let mut notifications = Vec::new();
-        let items = list.messages.unwrap_or_default();
-        debug!(count = items.len(), "gmail: list parsed");
-        for item in items {
-            if self.is_known(&item.id) {
-                continue;
+    /// Непрочитанные по запросу без истории и без отметки «показано» — для пробного прогона правил.
+    pub async fn peek_unread(&self, query: &str, limit: usize) -> Result<Vec<GmailNotification>> {
+        let page = self.list_messages(query, limit).await?;
+        let results: Vec<Result<GmailNotification>> = stream::iter(page.items)
+            .map(|item| async move { self.fetch_metadata(&item.id).await })
+            .buffered(FETCH_CONCURRENCY)
+            .collect()
+            .await;
+        results.into_iter().collect()
+    }
+
+    async fn list_messages(&self, query: &str, limit: usize) -> Result<MessagePage> {
+        let url = format!("{}/messages", self.api_url);
+        let limit = limit.max(1);
+        let mut page = MessagePage::default();
+        let mut page_token: Option<String> = None;
+        loop {
+            let token = self.token_provider.access_token().await?;
+            let page_size = (limit - page.items.len()).min(LIST_PAGE_SIZE).to_string();
+            debug!(%url, %query, ?page_token, "gmail: listing messages");
+            let mut request = self
+                .http
+                .get(&url)
+                .bearer_auth(token)
+                .query(&[
+                    ("q", query),
+                    ("maxResults", page_size.as_str()),
+                    ("labelIds", "UNREAD"),
+                ]);
+            if let Some(ref token) = page_token {
+                request = request.query(&[("pageToken", token.as_str())]);
             }
-            match self.fetch_message(&item.id).await {
-                Ok(notification) => {
-                    self.mark_seen(&notification.id);
-                    notifications.push(notification);
+            let response = self.send("list", request).await?;
+            let list: MessageList = response
+                .json()
+                .await
+                .context("invalid gmail list response")?;
+            page.items.extend(list.messages.unwrap_or_default());
+            match list.next_page_token {
+                Some(next) if page.items.len() < limit => page_token = Some(next),
+                Some(_) => {
+                    page.truncated = true;
+                    break;
                 }
-                Err(err) => warn!(%err, message_id = %item.id, "failed to fetch message"),
+                None => break,
             }
         }
-        notifications.sort_by_key(|n| n.received_at);
-        Ok(notifications)
+        Ok(page)
     }
 
-    pub fn forget(&self, id: &str) {
-        let mut guard = self.dedup.lock();
-        guard.pop(&id.to_string());
-    }
+    /// Возвращает `None`, если `start_history_id` устарел и нужна полная синхронизация.
+    async fn list_history(&self, start_history_id: &str) -> Result<Option<HistoryDelta>> {
+        let url = format!("{}/history", self.api_url);
+        let mut delta = HistoryDelta {
+            history_id: start_history_id.to_string(),
+            added: Vec::new(),
+        };
+        let mut page_token: Option<String> = None;
+        loop {
+            let token = self.token_provider.access_token().await?;
+            debug!(%url, %start_history_id, ?page_token, "gmail: listing history");
+            let mut request = self
+                .http
+                .get(&url)
+                .bearer_auth(token)
+                .query(&[
+                    ("startHistoryId", start_history_id),
+                    ("historyTypes", "messageAdded"),
+                    ("historyTypes", "labelAdded"),
+                ]);
+            if let Some(ref page) = page_token {
+                request = request.query(&[("pageToken", page.as_str())]);
+            }
+            let response = match self.send("history", request).await {
+                Ok(response) => response,
+                Err(err) if matches!(
+                    err.downcast_ref::<GmailApiError>(),
+                    Some(GmailApiError::Fatal { status, .. }) if *status == StatusCode::NOT_FOUND
+                ) => return Ok(None),
+                Err(err) => return Err(err),
+            };
 
-    pub fn clear_cache(&self) {
-        let mut guard = self.dedup.lock();
-        guard.clear();
+            let page: HistoryList = response
+                .json()
+                .await
+                .context("invalid gmail history response")?;
+            for message in page.added_messages() {
+                delta.add(message);
+            }
+            if let Some(history_id) = page.history_id {
+                delta.history_id = history_id;
+            }
+            match page.next_page_token {
+                Some(next) => page_token = Some(next),
+                None => break,
+            }
+        }
+        debug!(count = delta.added.len(), history_id = %delta.history_id, "gmail: history parsed");
+        Ok(Some(delta))
     }
 
-    async fn fetch_message(&self, id: &str) -> Result<GmailNotification> {
+    async fn current_history_id(&self) -> Result<String> {
         let token = self.token_provider.access_token().await?;
-        let url = format!("{}/messages/{}", GMAIL_API, id);
-        debug!(%id, %url, "gmail: fetch message");
+        let url = format!("{}/profile", self.api_url);
+        debug!(%url, "gmail: fetch profile");
         let response = self
-            .http
-            .get(url)
-            .bearer_auth(token)
-            .query(&[
-                ("format", "full"),
-            ])
-            .send()
+            .send("profile", self.http.get(url).bearer_auth(token))
+            .await?;
+
+        let profile: Profile = response
+            .json()
             .await
-            .context("failed to fetch gmail message")?;
+            .context("invalid gmail profile response")?;
+        Ok(profile.history_id)
+    }
 
-        debug!(%id, status = ?response.status(), "gmail: message response");
-        if response.status() == StatusCode::UNAUTHORIZED {
-            return Err(OAuthError::NotAuthorised.into());
-        }
+    /// Заголовки, сниппет и список вложений, без содержимого частей.
+    async fn fetch_metadata(&self, id: &str) -> Result<GmailNotification> {
+        let details = self
+            .get_message(id, &[("format", "full"), ("fields", SUMMARY_FIELDS)])
+            .await?;
+        Ok(details.into_notification(&self.account))
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            bail!("gmail message returned status {status} body={body}");
-        }
+    /// Содержимое вложения через `messages.attachments.get`.
+    pub async fn attachment(&self, message_id: &str, attachment_id: &str) -> Result<Vec<u8>> {
+        let token = self.token_provider.access_token().await?;
+        let url = format!("{}/messages/{}/attachments/{}", self.api_url, message_id, attachment_id);
+        debug!(%message_id, %url, "gmail: fetch attachment");
+        let response = self
+            .send("attachment", self.http.get(url).bearer_auth(token))
+            .await?;
+        let body: MessageBody = response
+            .json()
+            .await
+            .context("invalid gmail attachment response")?;
+        body.data
+            .as_deref()
+            .and_then(decode_base64url)
+            .context("gmail attachment has no data")
+    }
 
-        let details: Message = response
+    async fn get_message(&self, id: &str, query: &[(&str, &str)]) -> Result<Message> {
+        let token = self.token_provider.access_token().await?;
+        let url = format!("{}/messages/{}", self.api_url, id);
+        debug!(%id, %url, ?query, "gmail: fetch message");
+        let request = self.http.get(url).bearer_auth(token).query(query);
+        let response = self.send("message", request).await?;
+        response
             .json()
             .await
-            .context("invalid gmail message response")?;
-        debug!("fetch_message: получено сообщение id={}, payload.parts.len={}", details.id, details.payload.parts.len());
-        debug!("fetch_message: payload.body.is_some={}", details.payload.body.is_some());
-        if !details.payload.parts.is_empty() {
-            for (i, part) in details.payload.parts.iter().enumerate() {
-                debug!("fetch_message: часть[{}] mime_type={}, body.is_some={}, parts.len={}",
-                    i, part.mime_type, part.body.is_some(), part.parts.len());
+            .context("invalid gmail message response")
+    }
+
+    /// Тело письма для развёрнутого вида; последние открытые держим в памяти.
+    pub async fn message_body(&self, id: &str) -> Result<Option<MailBody>> {
+        if let Some(body) = self.bodies.lock().get(id) {
+            debug!(%id, "gmail: тело письма из кэша");
+            return Ok(Some(body));
+        }
+        let details = self.get_message(id, &[("format", "full")]).await?;
+        let mut body = extract_body(&details.payload);
+        if let Some(MailBody::Html(html)) = body.as_mut() {
+            if CID_REFERENCE.is_match(html) {
+                let images = self.inline_images(id, &details.payload).await;
+                *html = replace_cid_references(html, &images);
+            }
+        }
+        if let Some(ref body) = body {
+            self.bodies.lock().insert(id, body.clone());
+        }
+        Ok(body)
+    }
+
+    /// Встроенные картинки письма в виде data: URI по Content-ID. Крупные части
+    /// приходят без данных и догружаются как вложения; что не влезло в лимит — пропускаем.
+    async fn inline_images(&self, id: &str, payload: &MessagePayload) -> HashMap<String, String> {
+        let mut parts = Vec::new();
+        collect_inline_images(&payload.parts, &mut parts);
+        let mut budget = INLINE_IMAGES_LIMIT;
+        let mut images = HashMap::new();
+        for (content_id, part) in parts {
+            let Some(body) = part.body.as_ref() else { continue };
+            if body.size as usize > budget {
+                debug!(%id, %content_id, size = body.size, "gmail: встроенная картинка не влезает в лимит");
+                continue;
             }
+            let bytes = match (&body.data, &body.attachment_id) {
+                (Some(data), _) => decode_base64url(data),
+                (None, Some(attachment_id)) => match self.attachment(id, attachment_id).await {
+                    Ok(bytes) => Some(bytes),
+                    Err(err) => {
+                        warn!(%id, %content_id, %err, "gmail: failed to fetch inline image");
+                        None
+                    }
+                },
+                (None, None) => None,
+            };
+            let Some(bytes) = bytes.filter(|bytes| bytes.len() <= budget) else { continue };
+            budget -= bytes.len();
+            images.insert(
+                content_id,
+                format!("data:{};base64,{}", part.mime_type, base64.encode(&bytes)),
+            );
         }
-        Ok(details.into_notification())
+        images
+    }
+
+    async fn fetch_message(&self, id: &str) -> Result<GmailNotification> {
+        let details = self.get_message(id, &[("format", "full")]).await?;
+        Ok(details.into_notification(&self.account))
     }
 
     pub async fn mark_read(&self, id: &str) -> Result<()> {
+        self.modify("messages", id, &[], &["UNREAD"]).await
+    }
+
+    /// Непрочитано и не в корзине или спаме; удалённое письмо — нет.
+    pub async fn is_unread(&self, id: &str) -> Result<bool> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct MessageLabels {
+            #[serde(default)]
+            label_ids: Vec<String>,
+        }
         let token = self.token_provider.access_token().await?;
-        let url = format!("{}/messages/{}/modify", GMAIL_API, id);
-        debug!(%id, %url, "gmail: mark read");
-        let response = self
+        let url = format!("{}/messages/{}", self.api_url, id);
+        debug!(%id, %url, "gmail: check unread");
+        let request = self
+            .http
+            .get(url)
+            .bearer_auth(token)
+            .query(&[("format", "minimal"), ("fields", "labelIds")]);
+        let message: MessageLabels = match self.send("message", request).await {
+            Ok(response) => response.json().await.context("invalid gmail message response")?,
+            Err(err) if is_gone(&err) => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        Ok(matches_labels(&message.label_ids, &[]))
+    }
+
+    /// Снимает `UNREAD` со всех писем цепочки через `threads.modify`.
+    pub async fn mark_thread_read(&self, thread_id: &str) -> Result<()> {
+        self.modify("threads", thread_id, &[], &["UNREAD"]).await
+    }
+
+    /// `resource` — `messages` или `threads`: действие над письмом или всей цепочкой.
+    async fn act(&self, resource: &str, id: &str, action: &MessageAction) -> Result<()> {
+        match label_changes(action) {
+            Some((add, remove)) => self.modify(resource, id, &add, &remove).await,
+            None => {
+                let token = self.token_provider.access_token().await?;
+                let url = format!("{}/{}/{}/trash", self.api_url, resource, id);
+                debug!(%id, %url, "gmail: trash");
+                self.send("trash", self.http.post(url).bearer_auth(token)).await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn modify(&self, resource: &str, id: &str, add: &[&str], remove: &[&str]) -> Result<()> {
+        let token = self.token_provider.access_token().await?;
+        let url = format!("{}/{}/{}/modify", self.api_url, resource, id);
+        debug!(%id, %url, ?add, ?remove, "gmail: modify labels");
+        let request = self
             .http
             .post(url)
             .bearer_auth(token)
-            .json(&serde_json::json!({ "removeLabelIds": ["UNREAD"] }))
-            .send()
-            .await
-            .context("failed to modify message")?;
+            .json(&serde_json::json!({ "addLabelIds": add, "removeLabelIds": remove }));
+        self.send("modify", request).await?;
+        Ok(())
+    }
+
+    /// Пользовательские метки ящика; системные доступны отдельными действиями.
+    pub async fn labels(&self) -> Result<Vec<MailLabel>> {
+        #[derive(Deserialize)]
+        struct LabelsResponse {
+            #[serde(default)]
+            labels: Vec<LabelResource>,
+        }
+        #[derive(Deserialize)]
+        struct LabelResource {
+            id: String,
+            name: String,
+            #[serde(rename = "type", default)]
+            kind: String,
+        }
+
+        let token = self.token_provider.access_token().await?;
+        let url = format!("{}/labels", self.api_url);
+        let response = self.send("labels", self.http.get(url).bearer_auth(token)).await?;
+        let body: LabelsResponse = response.json().await.context("invalid gmail labels response")?;
+        let mut labels: Vec<MailLabel> = body
+            .labels
+            .into_iter()
+            .filter(|label| label.kind == "user")
+            .map(|label| MailLabel {
+                id: label.id,
+                name: label.name,
+            })
+            .collect();
+        labels.sort_by_key(|label| label.name.to_lowercase());
+        Ok(labels)
+    }
+
+    /// Ответ в ту же цепочку через `messages.send`. Нужен scope `gmail.send`,
+    /// без него Gmail отвечает 403 — просим включить быстрые ответы и войти заново.
+    pub async fn send_reply(&self, id: &str, body: &str) -> Result<()> {
+        let mut query = vec![("format", "metadata")];
+        query.extend(REPLY_HEADERS.iter().map(|name| ("metadataHeaders", *name)));
+        let original = self.get_message(id, &query).await?;
+        let raw = original.reply(&self.account, body, Utc::now())?;
+
+        let token = self.token_provider.access_token().await?;
+        let url = format!("{}/messages/send", self.api_url);
+        debug!(%id, %url, thread_id = %original.thread_id, "gmail: send reply");
+        let request = self.http.post(url).bearer_auth(token).json(&serde_json::json!({
+            "raw": URL_SAFE.encode(raw),
+            "threadId": original.thread_id,
+        }));
+        match self.send("send", request).await {
+            Ok(_) => Ok(()),
+            Err(err)
+                if matches!(
+                    err.downcast_ref::<GmailApiError>(),
+                    Some(GmailApiError::Fatal { status: StatusCode::FORBIDDEN, .. })
+                ) =>
+            {
+                Err(err.context("нет разрешения на отправку: включите быстрые ответы в настройках и войдите заново"))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Общая точка отправки запросов к API: 401 — переавторизация, 429/5xx и сетевые
+    /// сбои — отступ с учётом `Retry-After`, остальное — фатальная ошибка запроса.
+    async fn send(&self, what: &'static str, request: RequestBuilder) -> Result<Response> {
+        if let Some((retry_at, reason)) = self.backoff.lock().blocked(Utc::now()) {
+            debug!(what, %retry_at, "gmail: запрос пропущен, ждём окончания backoff");
+            return Err(GmailApiError::Transient { reason, retry_at }.into());
+        }
 
-        debug!(%id, status = ?response.status(), "gmail: mark read response");
-        if response.status() == StatusCode::UNAUTHORIZED {
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(err) if err.is_connect() || err.is_timeout() => {
+                return Err(self.transient(format!("{what}: {err}"), None).into());
+            }
+            Err(err) => return Err(anyhow::Error::new(err).context(format!("gmail {what} request failed"))),
+        };
+
+        let status = response.status();
+        debug!(what, ?status, "gmail: response");
+        if status.is_success() {
+            if self.backoff.lock().succeed() {
+                info!(account = %self.account, "gmail: API снова отвечает");
+            }
+            return Ok(response);
+        }
+        if status == StatusCode::UNAUTHORIZED {
             return Err(OAuthError::NotAuthorised.into());
         }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            bail!("gmail modify returned status {status} body={body}");
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, Utc::now()));
+        let body = response.text().await.unwrap_or_default();
+        if is_transient_status(status, &body) {
+            return Err(self.transient(format!("{what} returned status {status}"), retry_after).into());
         }
+        Err(GmailApiError::Fatal { what, status, body }.into())
+    }
 
-        Ok(())
+    fn transient(&self, reason: String, retry_after: Option<Duration>) -> GmailApiError {
+        let retry_at = self.backoff.lock().fail(Utc::now(), retry_after, reason.clone());
+        warn!(account = %self.account, %reason, %retry_at, "gmail: временный сбой API, откладываем запросы");
+        GmailApiError::Transient { reason, retry_at }
     }
 
     fn is_known(&self, id: &str) -> bool {
-        let mut guard = self.dedup.lock();
-        guard.get(&id.to_string()).is_some()
+        self.seen.is_known(id)
+    }
+}
+
+#[async_trait]
+impl MailProvider for GmailClient {
+    async fn list_new(&self, query: &str, limit: usize) -> Result<Vec<GmailNotification>> {
+        self.fetch_unread(query, limit).await
+    }
+
+    async fn peek(&self, query: &str, limit: usize) -> Result<Vec<GmailNotification>> {
+        self.peek_unread(query, limit).await
+    }
+
+    async fn fetch(&self, id: &str) -> Result<GmailNotification> {
+        self.fetch_message(id).await
+    }
+
+    async fn body(&self, id: &str) -> Result<Option<MailBody>> {
+        self.message_body(id).await
+    }
+
+    async fn attachment(&self, message_id: &str, attachment_id: &str) -> Result<Vec<u8>> {
+        GmailClient::attachment(self, message_id, attachment_id).await
+    }
+
+    async fn is_unread(&self, id: &str) -> Result<bool> {
+        GmailClient::is_unread(self, id).await
+    }
+
+    async fn mark_read(&self, id: &str) -> Result<()> {
+        GmailClient::mark_read(self, id).await
+    }
+
+    async fn mark_thread_read(&self, thread_id: &str, _ids: &[String]) -> Result<()> {
+        GmailClient::mark_thread_read(self, thread_id).await
+    }
+
+    async fn apply(&self, id: &str, action: &MessageAction) -> Result<()> {
+        self.act("messages", id, action).await
+    }
+
+    async fn apply_to_thread(&self, thread_id: &str, _ids: &[String], action: &MessageAction) -> Result<()> {
+        self.act("threads", thread_id, action).await
+    }
+
+    async fn labels(&self) -> Result<Vec<MailLabel>> {
+        GmailClient::labels(self).await
+    }
+
+    async fn send_reply(&self, id: &str, body: &str) -> Result<()> {
+        GmailClient::send_reply(self, id, body).await
+    }
+
+    fn record_outcome(&self, id: &str, outcome: NotificationOutcome) {
+        self.seen.record_outcome(id, outcome);
     }
 
-    fn mark_seen(&self, id: &str) {
-        let mut guard = self.dedup.lock();
-        guard.put(id.to_string(), Instant::now());
+    fn forget(&self, id: &str) {
+        self.seen.forget(id);
     }
+
+    fn reset_sync(&self) {
+        *self.history_id.lock() = None;
+    }
+}
+
+/// Несколько последних загруженных тел писем, чтобы повторное раскрытие не ходило в API.
+#[derive(Default)]
+struct BodyCache {
+    entries: VecDeque<(String, MailBody)>,
+}
+
+impl BodyCache {
+    fn get(&self, id: &str) -> Option<MailBody> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key == id)
+            .map(|(_, body)| body.clone())
+    }
+
+    fn insert(&mut self, id: &str, body: MailBody) {
+        self.entries.retain(|(key, _)| key != id);
+        if self.entries.len() >= BODY_CACHE_SIZE {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((id.to_string(), body));
+    }
+}
+
+/// Ссылка в веб-интерфейс нужного ящика. Gmail принимает e-mail вместо номера
+/// `/mail/u/N/`, поэтому порядок входа в браузере не важен.
+fn mailbox_url(account: &str, fragment: &str) -> String {
+    format!(
+        "https://mail.google.com/mail/u/{}/#{fragment}",
+        urlencoding::encode(account)
+    )
+}
+
+/// Gmail отвечает на превышение квоты не только 429, но и 403 с причиной `*RateLimitExceeded`.
+fn is_transient_status(status: StatusCode, body: &str) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+        || (status == StatusCode::FORBIDDEN && (body.contains("rateLimitExceeded") || body.contains("RateLimitExceeded")))
+}
+
+fn is_gone(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<GmailApiError>(),
+        Some(GmailApiError::Fatal { status, .. }) if *status == StatusCode::NOT_FOUND || *status == StatusCode::GONE
+    )
+}
+
+/// Метки, к которым сводится запрос Gmail, или `None`, если в нём есть другие условия.
+fn query_labels(query: &str) -> Option<Vec<String>> {
+    query
+        .split_whitespace()
+        .map(|term| {
+            let label = match term.to_lowercase().as_str() {
+                "in:inbox" => "INBOX",
+                "is:unread" => "UNREAD",
+                "is:starred" => "STARRED",
+                "is:important" => "IMPORTANT",
+                "category:primary" => "CATEGORY_PERSONAL",
+                "category:social" => "CATEGORY_SOCIAL",
+                "category:promotions" => "CATEGORY_PROMOTIONS",
+                "category:updates" => "CATEGORY_UPDATES",
+                "category:forums" => "CATEGORY_FORUMS",
+                _ => return None,
+            };
+            Some(label.to_string())
+        })
+        .collect()
+}
+
+/// Непрочитанное письмо с нужными метками; черновики, спам и корзину поиск Gmail тоже не показывает.
+fn matches_labels(labels: &[String], required: &[String]) -> bool {
+    let has = |label: &str| labels.iter().any(|l| l == label);
+    has("UNREAD")
+        && !["DRAFT", "SPAM", "TRASH"].into_iter().any(has)
+        && required.iter().all(|label| has(label))
+}
+
+/// Сводное уведомление «и ещё N писем» не соответствует письму в Gmail.
+pub fn is_summary_id(id: &str) -> bool {
+    id.starts_with(SUMMARY_ID_PREFIX)
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct MessageList {
     messages: Option<Vec<MessageRef>>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct MessagePage {
+    items: Vec<MessageRef>,
+    truncated: bool,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct MessageRef {
     id: String,
+    /// Есть в записях истории, в `messages.list` — нет.
+    #[serde(default)]
+    label_ids: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Profile {
+    history_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryList {
+    #[serde(default)]
+    history: Vec<HistoryRecord>,
+    next_page_token: Option<String>,
+    history_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryRecord {
+    #[serde(default)]
+    messages_added: Vec<HistoryMessage>,
+    #[serde(default)]
+    labels_added: Vec<HistoryLabelChange>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryMessage {
+    message: MessageRef,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryLabelChange {
+    message: MessageRef,
+    #[serde(default)]
+    label_ids: Vec<String>,
+}
+
+impl HistoryList {
+    /// Письма, которые появились или снова стали непрочитанными, с их метками на тот момент.
+    fn added_messages(&self) -> impl Iterator<Item = &MessageRef> + '_ {
+        self.history.iter().flat_map(|record| {
+            let added = record.messages_added.iter().map(|m| &m.message);
+            let unread_again = record
+                .labels_added
+                .iter()
+                .filter(|change| change.label_ids.iter().any(|label| label == "UNREAD"))
+                .map(|change| &change.message);
+            added.chain(unread_again)
+        })
+    }
+}
+
+struct HistoryDelta {
+    history_id: String,
+    /// В порядке появления; метки — из последней записи о письме.
+    added: Vec<(String, Vec<String>)>,
+}
+
+impl HistoryDelta {
+    fn add(&mut self, message: &MessageRef) {
+        match self.added.iter_mut().find(|(id, _)| *id == message.id) {
+            Some((_, labels)) => labels.clone_from(&message.label_ids),
+            None => self.added.push((message.id.clone(), message.label_ids.clone())),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -204,6 +1057,8 @@ struct MessageRef {
 struct Message {
     id: String,
     thread_id: String,
+    #[serde(default)]
+    label_ids: Vec<String>,
     snippet: Option<String>,
     internal_date: Option<String>,
     payload: MessagePayload,
@@ -211,6 +1066,8 @@ struct Message {
 
 #[derive(Debug, Deserialize)]
 struct MessagePayload {
+    #[serde(rename = "mimeType", default)]
+    mime_type: String,
     headers: Vec<Header>,
     #[serde(default)]
     parts: Vec<MessagePart>,
@@ -221,6 +1078,10 @@ struct MessagePayload {
 struct MessagePart {
     #[serde(rename = "mimeType")]
     mime_type: String,
+    #[serde(default)]
+    filename: String,
+    #[serde(default)]
+    headers: Vec<Header>,
     body: Option<MessageBody>,
     #[serde(default)]
     parts: Vec<MessagePart>,
@@ -229,182 +1090,331 @@ struct MessagePart {
 #[derive(Debug, Clone, Deserialize)]
 struct MessageBody {
     data: Option<String>,
+    #[serde(rename = "attachmentId")]
+    attachment_id: Option<String>,
+    #[serde(default)]
+    size: u64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 struct Header {
     name: String,
     value: String,
 }
 
 impl Message {
-    fn into_notification(self) -> GmailNotification {
+    fn into_notification(self, account: &str) -> GmailNotification {
         let subject = self
             .payload
             .headers
             .iter()
             .find(|h| h.name.eq_ignore_ascii_case("Subject"))
-            .map(|h| h.value.clone())
+            .map(|h| decode_header(&h.value))
             .unwrap_or_else(|| "(без темы)".to_string());
-        let sender = self
-            .payload
-            .headers
-            .iter()
-            .find(|h| h.name.eq_ignore_ascii_case("From"))
-            .map(|h| h.value.clone());
-        let recipient = self
-            .payload
-            .headers
-            .iter()
-            .find(|h| h.name.eq_ignore_ascii_case("To"))
-            .map(|h| h.value.clone());
-        let received_at = self
-            .payload
-            .headers
-            .iter()
-            .find(|h| h.name.eq_ignore_ascii_case("Date"))
-            .and_then(|h| DateTime::parse_from_rfc2822(&h.value).ok())
-            .map(|dt| dt.with_timezone(&Utc));
-        let url = format!(
-            "https://mail.google.com/mail/u/0/#inbox/{message}",
-            message = self.id
-        );
+        // Заголовков To/Cc бывает несколько, адреса из всех собираем в один список
+        let address_list = |name: &str| -> Vec<EmailAddress> {
+            self.payload
+                .headers
+                .iter()
+                .filter(|h| h.name.eq_ignore_ascii_case(name))
+                .flat_map(|h| parse_address_list(&h.value))
+                .collect()
+        };
+        let from = address_list("From").into_iter().next();
+        let to = address_list("To");
+        let cc = address_list("Cc");
+        let reply_to = address_list("Reply-To");
+        let raw_header = |name: &str| {
+            self.payload
+                .headers
+                .iter()
+                .find(|h| h.name.eq_ignore_ascii_case(name))
+                .map(|h| decode_header(&h.value))
+        };
+        let sender = from.as_ref().map(EmailAddress::display).or_else(|| raw_header("From"));
+        let recipient = join_display(&to).or_else(|| raw_header("To"));
+        let header_date = raw_header("Date").and_then(|value| parse_date_header(&value));
+        let internal_date = self
+            .internal_date
+            .as_deref()
+            .and_then(|ms| ms.parse::<i64>().ok())
+            .and_then(DateTime::from_timestamp_millis);
+        let url = mailbox_url(account, &format!("inbox/{}", self.id));
 
-        // Извлекаем тело письма (приоритет: HTML, затем plain text)
-        let body = extract_body(&self.payload);
-        debug!("into_notification: тело письма извлечено, есть ли body = {}", body.is_some());
-        if let Some(ref b) = body {
-            debug!("into_notification: длина тела = {}", b.len());
-        }
+        // Содержимое частей в ответе с `fields` не приходит, о теле судим по дереву частей
+        let has_body = is_text_mime(&self.payload.mime_type) || has_text_part(&self.payload.parts);
+        let mut attachments = Vec::new();
+        collect_attachments(&self.payload.parts, &mut attachments);
 
         GmailNotification {
             id: self.id,
+            account: account.to_string(),
             thread_id: self.thread_id,
             subject,
             snippet: self.snippet,
             sender,
             recipient,
-            received_at,
+            from,
+            to,
+            cc,
+            reply_to,
+            received_at: internal_date.or(header_date),
+            header_date,
+            internal_date,
+            received_local: None,
+            received_relative: None,
             url,
-            body,
+            attachments,
+            merged_ids: Vec::new(),
+            labels: self.label_ids,
+            sound_path: None,
+            priority: false,
+            vip: false,
+            silent: false,
+            has_body,
+            mailbox_actions: true,
+            summary: false,
         }
     }
-}
 
-fn extract_body(payload: &MessagePayload) -> Option<String> {
-    debug!("extract_body: начало извлечения тела письма");
-    debug!("extract_body: количество частей = {}", payload.parts.len());
+    /// Текст ответа в формате RFC 822: адресат из `Reply-To` или `From`, тема с `Re:`,
+    /// `In-Reply-To`/`References` по `Message-ID` исходного письма. Тело — text/plain в base64.
+    fn reply(&self, account: &str, body: &str, now: DateTime<Utc>) -> Result<String> {
+        let header = |name: &str| {
+            self.payload
+                .headers
+                .iter()
+                .find(|h| h.name.eq_ignore_ascii_case(name))
+                .map(|h| h.value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let recipients = header("Reply-To")
+            .or_else(|| header("From"))
+            .map(|value| parse_address_list(&value))
+            .unwrap_or_default();
+        anyhow::ensure!(!recipients.is_empty(), "у письма {} нет адреса для ответа", self.id);
 
-    // Логируем структуру частей для отладки
-    for (i, part) in payload.parts.iter().enumerate() {
-        debug!("extract_body: часть[{}]: mime_type={}, has_body={}, has_parts={}",
-            i, part.mime_type, part.body.is_some(), !part.parts.is_empty());
-    }
+        let subject = header("Subject").map(|value| decode_header(&value)).unwrap_or_default();
+        let subject = if subject.get(..3).is_some_and(|prefix| prefix.eq_ignore_ascii_case("re:")) {
+            subject
+        } else {
+            format!("Re: {subject}")
+        };
+        let message_id = header("Message-ID");
+        let references = match (header("References"), &message_id) {
+            (Some(references), Some(message_id)) => Some(format!("{references} {message_id}")),
+            (references, message_id) => references.or(message_id.clone()),
+        };
 
-    // Сначала пытаемся найти HTML версию
-    if let Some(html) = find_part_by_mime(payload, "text/html") {
-        debug!("extract_body: найден HTML");
-        let decoded = decode_body(&html);
-        debug!("extract_body: HTML декодирован, результат = {}", decoded.is_some());
-        if decoded.is_some() {
-            return decoded;
+        let to: Vec<String> = recipients.iter().map(EmailAddress::to_header).collect();
+        let mut lines = vec![
+            format!("From: {account}"),
+            format!("To: {}", to.join(", ")),
+            format!("Subject: {}", encode_header(&subject)),
+            format!("Date: {}", now.to_rfc2822()),
+        ];
+        if let Some(message_id) = message_id {
+            lines.push(format!("In-Reply-To: {message_id}"));
         }
-    }
-
-    // Затем пытаемся найти plain text
-    if let Some(text) = find_part_by_mime(payload, "text/plain") {
-        debug!("extract_body: найден plain text");
-        let decoded = decode_body(&text);
-        debug!("extract_body: plain text декодирован, результат = {}", decoded.is_some());
-        if decoded.is_some() {
-            return decoded;
+        if let Some(references) = references {
+            lines.push(format!("References: {references}"));
         }
+        lines.push("MIME-Version: 1.0".into());
+        lines.push("Content-Type: text/plain; charset=utf-8".into());
+        lines.push("Content-Transfer-Encoding: base64".into());
+
+        let text = body.replace("\r\n", "\n").replace('\n', "\r\n");
+        let encoded = base64.encode(text);
+        let wrapped: Vec<&str> = encoded
+            .as_bytes()
+            .chunks(76)
+            .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+            .collect();
+        Ok(format!("{}\r\n\r\n{}\r\n", lines.join("\r\n"), wrapped.join("\r\n")))
     }
+}
 
-    // Если нет частей, проверяем body непосредственно в payload
-    if let Some(ref body) = payload.body {
-        debug!("extract_body: используем body из payload");
-        let decoded = decode_body(body);
-        debug!("extract_body: body декодирован, результат = {}", decoded.is_some());
-        if decoded.is_some() {
-            return decoded;
-        }
+/// `Date` с комментарием в конце (`+0300 (MSK)`) chrono не разбирает.
+fn parse_date_header(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.split('(').next().unwrap_or(value).trim();
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// «только что», «5 мин назад», «3 ч назад», «вчера в 18:40» или дата.
+/// Время из будущего (кривые часы отправителя) считаем только что пришедшим.
+fn relative_time<Tz: TimeZone>(at: DateTime<Utc>, now: DateTime<Utc>, tz: &Tz) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let elapsed = now - at;
+    if elapsed < chrono::Duration::minutes(1) {
+        return "только что".to_string();
+    }
+    if elapsed < chrono::Duration::hours(1) {
+        return format!("{} мин назад", elapsed.num_minutes());
+    }
+    let local = at.with_timezone(tz);
+    let today = now.with_timezone(tz).date_naive();
+    if local.date_naive() == today {
+        return format!("{} ч назад", elapsed.num_hours());
     }
+    if today.pred_opt() == Some(local.date_naive()) {
+        return local.format("вчера в %H:%M").to_string();
+    }
+    if local.year() == today.year() {
+        return local.format("%d.%m в %H:%M").to_string();
+    }
+    local.format("%d.%m.%Y").to_string()
+}
 
-    debug!("extract_body: тело письма не найдено");
-    None
+/// Метки, которые добавляет и снимает действие; `None` — это не метки, а корзина.
+fn label_changes(action: &MessageAction) -> Option<(Vec<&str>, Vec<&str>)> {
+    Some(match action {
+        MessageAction::Archive => (vec![], vec!["INBOX"]),
+        MessageAction::Trash => return None,
+        MessageAction::Star => (vec!["STARRED"], vec![]),
+        MessageAction::Important(true) => (vec!["IMPORTANT"], vec![]),
+        MessageAction::Important(false) => (vec![], vec!["IMPORTANT"]),
+        MessageAction::Label(id) => (vec![id.as_str()], vec![]),
+    })
 }
 
-fn find_part_by_mime(payload: &MessagePayload, mime_type: &str) -> Option<MessageBody> {
-    debug!("find_part_by_mime: ищем тип {}", mime_type);
-    // Рекурсивный поиск по частям
-    for part in &payload.parts {
-        debug!("find_part_by_mime: проверяем часть с типом {}", part.mime_type);
+fn is_text_mime(mime_type: &str) -> bool {
+    matches!(mime_type, "text/plain" | "text/html")
+}
 
-        // Если это multipart контейнер, сначала проверяем его подчасти
-        if part.mime_type.starts_with("multipart/") {
-            debug!("find_part_by_mime: найден multipart контейнер, проверяем {} вложенных частей", part.parts.len());
-            if !part.parts.is_empty() {
-                let nested_payload = MessagePayload {
-                    headers: vec![],
-                    parts: part.parts.clone(),
-                    body: None,
-                };
-                if let Some(body) = find_part_by_mime(&nested_payload, mime_type) {
-                    return Some(body);
-                }
-            }
-            continue;
-        }
-
-        // Проверяем совпадение типа
-        if part.mime_type == mime_type {
-            if let Some(ref body) = part.body {
-                debug!("find_part_by_mime: найдено тело для типа {}, data.is_some={}", mime_type, body.data.is_some());
-                if let Some(ref data) = body.data {
-                    debug!("find_part_by_mime: длина data={}", data.len());
-                    // Возвращаем только если есть данные
-                    if !data.is_empty() {
-                        return Some(MessageBody { data: body.data.clone() });
-                    }
-                }
-            } else {
-                debug!("find_part_by_mime: у части с типом {} нет body", mime_type);
+/// Есть ли среди частей текст или HTML, не считая вложенных файлов.
+fn has_text_part(parts: &[MessagePart]) -> bool {
+    parts
+        .iter()
+        .any(|part| (part.filename.is_empty() && is_text_mime(&part.mime_type)) || has_text_part(&part.parts))
+}
+
+fn collect_attachments(parts: &[MessagePart], attachments: &mut Vec<Attachment>) {
+    for part in parts {
+        if let Some(id) = part.body.as_ref().and_then(|body| body.attachment_id.clone()) {
+            if !part.filename.is_empty() {
+                attachments.push(Attachment {
+                    id,
+                    filename: decode_header(&part.filename),
+                    mime_type: part.mime_type.clone(),
+                    size: part.body.as_ref().map_or(0, |body| body.size),
+                });
             }
         }
+        collect_attachments(&part.parts, attachments);
+    }
+}
 
-        // Рекурсивный поиск во вложенных частях
-        if !part.parts.is_empty() {
-            debug!("find_part_by_mime: у части есть {} вложенных частей", part.parts.len());
-            let nested_payload = MessagePayload {
-                headers: vec![],
-                parts: part.parts.clone(),
-                body: None,
-            };
-            if let Some(body) = find_part_by_mime(&nested_payload, mime_type) {
-                return Some(body);
+/// Части `image/*` с заголовком Content-ID (без угловых скобок).
+fn collect_inline_images<'a>(parts: &'a [MessagePart], images: &mut Vec<(String, &'a MessagePart)>) {
+    for part in parts {
+        if part.mime_type.to_ascii_lowercase().starts_with("image/") {
+            let content_id = part
+                .headers
+                .iter()
+                .find(|h| h.name.eq_ignore_ascii_case("Content-ID"))
+                .map(|h| h.value.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+                .filter(|id| !id.is_empty());
+            if let Some(content_id) = content_id {
+                images.push((content_id, part));
             }
         }
+        collect_inline_images(&part.parts, images);
+    }
+}
+
+static CID_REFERENCE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?i)\bcid:([^"'\s>)]+)"#).unwrap());
+
+/// Заменяет ссылки `cid:` на найденные картинки, остальные оставляет как есть.
+fn replace_cid_references(html: &str, images: &HashMap<String, String>) -> String {
+    CID_REFERENCE
+        .replace_all(html, |caps: &regex::Captures| {
+            let reference = urlencoding::decode(&caps[1])
+                .map(|id| id.into_owned())
+                .unwrap_or_else(|_| caps[1].to_string());
+            images
+                .get(&reference)
+                .cloned()
+                .unwrap_or_else(|| caps[0].to_string())
+        })
+        .into_owned()
+}
+
+fn extract_body(payload: &MessagePayload) -> Option<MailBody> {
+    // Сначала HTML, затем plain text, затем тело самого payload, если частей нет
+    let decode_part = |mime_type| {
+        let part = find_part_by_mime(&payload.parts, mime_type)?;
+        decode_body(part.body.as_ref()?, &part.headers)
+    };
+    if let Some(html) = decode_part("text/html") {
+        return Some(MailBody::Html(html));
+    }
+    if let Some(text) = decode_part("text/plain") {
+        return Some(MailBody::Text(text));
+    }
+    let body = decode_body(payload.body.as_ref()?, &payload.headers)?;
+    Some(match payload.mime_type.as_str() {
+        "text/html" => MailBody::Html(body),
+        _ => MailBody::Text(body),
+    })
+}
+
+/// Первая часть нужного типа с непустыми данными, с обходом вложенных частей.
+fn find_part_by_mime<'a>(parts: &'a [MessagePart], mime_type: &str) -> Option<&'a MessagePart> {
+    parts.iter().find_map(|part| {
+        let has_data = part
+            .body
+            .as_ref()
+            .and_then(|body| body.data.as_deref())
+            .is_some_and(|data| !data.is_empty());
+        if part.mime_type == mime_type && has_data {
+            return Some(part);
+        }
+        find_part_by_mime(&part.parts, mime_type)
+    })
+}
+
+/// Gmail уже снял Content-Transfer-Encoding (quoted-printable/base64 письма):
+/// в `data` лежат исходные байты части в base64url, остаётся перекодировать их из
+/// кодировки `Content-Type: ...; charset=`. Битые последовательности заменяются, а не теряют тело.
+fn decode_body(body: &MessageBody, headers: &[Header]) -> Option<String> {
+    let data = body.data.as_ref()?;
+    let bytes = decode_base64url(data)?;
+
+    let charset = content_type_charset(headers);
+    let encoding = charset
+        .as_deref()
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8);
+    let (text, _, had_errors) = encoding.decode(&bytes);
+    if had_errors {
+        warn!(?charset, encoding = encoding.name(), "decode_body: некорректные байты заменены");
     }
+    Some(text.into_owned())
+}
 
-    debug!("find_part_by_mime: тип {} не найден", mime_type);
-    None
+/// Gmail использует URL-safe Base64, паддинг бывает опущен.
+fn decode_base64url(data: &str) -> Option<Vec<u8>> {
+    let mut replaced = data.replace('-', "+").replace('_', "/");
+    while !replaced.len().is_multiple_of(4) {
+        replaced.push('=');
+    }
+    base64.decode(&replaced).ok()
 }
 
-fn decode_body(body: &MessageBody) -> Option<String> {
-    debug!("decode_body: начало декодирования, есть ли data = {}", body.data.is_some());
-    body.data.as_ref().and_then(|data| {
-        debug!("decode_body: длина закодированных данных = {}", data.len());
-        // Gmail использует URL-safe Base64
-        let replaced = data.replace('-', "+").replace('_', "/");
-        let decoded = base64.decode(&replaced)
-            .ok()
-            .and_then(|bytes| {
-                debug!("decode_body: декодировано {} байт", bytes.len());
-                String::from_utf8(bytes).ok()
-            });
-        debug!("decode_body: результат декодирования = {}", decoded.is_some());
-        decoded
+/// Параметр `charset` из заголовка `Content-Type` части.
+fn content_type_charset(headers: &[Header]) -> Option<String> {
+    let content_type = headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case("Content-Type"))?;
+    content_type.value.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"').to_string())
     })
 }
 
@@ -422,6 +1432,7 @@ pub async fn wait_for_authorisation(token_provider: Arc<dyn AccessTokenProvider>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fake_gmail::{FakeGmail, FAKE_ACCESS_TOKEN};
 
     #[test]
     fn parses_camel_case_message_payload() {
@@ -446,13 +1457,332 @@ mod tests {
         assert_eq!(message.thread_id, "thread-1");
         assert_eq!(message.snippet.as_deref(), Some("hello"));
 
-        let notification = message.into_notification();
+        let notification = message.into_notification("me@example.com");
         assert_eq!(notification.subject, "Test subject");
         assert_eq!(
             notification.sender.as_deref(),
             Some("Sender <sender@example.com>")
         );
+        assert_eq!(notification.from.map(|from| from.email).as_deref(), Some("sender@example.com"));
         assert_eq!(notification.thread_id, "thread-1");
+        assert_eq!(
+            notification.url,
+            "https://mail.google.com/mail/u/me%40example.com/#inbox/abc"
+        );
         assert!(notification.received_at.is_some(), "date header converted");
     }
+
+    #[test]
+    fn picks_received_at_and_formats_relative_time() {
+        let header = DateTime::parse_from_rfc3339("2024-10-24T15:30:00Z").unwrap().with_timezone(&Utc);
+        let internal = DateTime::from_timestamp_millis(1_729_784_000_000).unwrap();
+        let mut notification = GmailNotification::for_test("msg-1", "thread-1");
+        notification.header_date = Some(header);
+        notification.apply_received_at(ReceivedAtSource::DateHeader);
+        assert_eq!(notification.received_at, Some(header), "internalDate missing, header used");
+        notification.internal_date = Some(internal);
+        notification.apply_received_at(ReceivedAtSource::InternalDate);
+        assert_eq!(notification.received_at, Some(internal));
+        notification.header_date = None;
+        notification.apply_received_at(ReceivedAtSource::DateHeader);
+        assert_eq!(notification.received_at, Some(internal), "falls back to internalDate");
+        assert_eq!(
+            parse_date_header("Tue, 14 Oct 2025 09:15:00 +0300 (MSK)"),
+            Some(DateTime::parse_from_rfc3339("2025-10-14T06:15:00Z").unwrap().with_timezone(&Utc))
+        );
+
+        // Москва, UTC+3: 23:30 по Гринвичу — уже следующий день
+        let msk = chrono::FixedOffset::east_opt(3 * 3600).unwrap();
+        let now = DateTime::parse_from_rfc3339("2025-03-10T23:30:00Z").unwrap().with_timezone(&Utc);
+        let ago = |minutes: i64| relative_time(now - chrono::Duration::minutes(minutes), now, &msk);
+        assert_eq!(ago(0), "только что");
+        assert_eq!(ago(-10), "только что");
+        assert_eq!(ago(5), "5 мин назад");
+        assert_eq!(ago(90), "1 ч назад");
+        assert_eq!(ago(3 * 60), "вчера в 23:30");
+        assert_eq!(ago(3 * 24 * 60), "08.03 в 02:30");
+        assert_eq!(ago(400 * 24 * 60), "05.02.2024");
+    }
+
+    struct StaticToken;
+
+    #[async_trait]
+    impl AccessTokenProvider for StaticToken {
+        async fn access_token(&self) -> Result<String, OAuthError> {
+            Ok(FAKE_ACCESS_TOKEN.to_string())
+        }
+    }
+
+    /// Временный каталог, который удаляется и при упавшем assert.
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("gmail-test-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn fixtures() -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/fake-gmail")
+    }
+
+    /// Фейковый сервер с фикстурами и клиент к нему; seen-файл живёт в `TempDir`.
+    async fn fake_client(fixtures: &std::path::Path) -> (FakeGmail, GmailClient, TempDir) {
+        let server = FakeGmail::start(fixtures, "127.0.0.1:0".parse().unwrap())
+            .await
+            .expect("fake gmail starts");
+        let dir = TempDir::new();
+        let client = GmailClient::new(
+            "fake@example.com",
+            server.api_url(),
+            Arc::new(StaticToken),
+            Arc::new(SeenStore::open(dir.0.join("seen.json"))),
+        )
+        .unwrap();
+        (server, client, dir)
+    }
+
+    #[tokio::test]
+    async fn polls_and_marks_read_against_fake_server() {
+        let (server, client, _dir) = fake_client(&fixtures()).await;
+
+        let first = client.list_new("in:inbox is:unread", 50).await.expect("first poll");
+        assert_eq!(first.len(), server.unread_ids().len());
+        assert!(first.iter().all(|n| n.account == "fake@example.com" && n.has_body && n.mailbox_actions));
+        // Для тоста приходит структура частей и вложения, но не их содержимое
+        let summary = client
+            .get_message("msg-001", &[("format", "full"), ("fields", SUMMARY_FIELDS)])
+            .await
+            .unwrap();
+        assert!(summary.payload.parts[0].parts.iter().all(|p| p.body.as_ref().is_some_and(|b| b.data.is_none())));
+        let invoice_body = summary.payload.parts[1].body.as_ref().unwrap();
+        assert_eq!(invoice_body.attachment_id.as_deref(), Some("att-invoice-001"));
+        let body = client.body(&first[0].id).await.expect("body loads");
+        assert!(body.is_some(), "body is loaded on demand");
+
+        let invoice = first
+            .iter()
+            .flat_map(|n| n.attachments.iter().map(move |a| (n, a)))
+            .find(|(_, a)| a.filename == "invoice-october.pdf")
+            .expect("attachment listed at toast time");
+        let bytes = client.attachment(&invoice.0.id, &invoice.1.id).await.expect("attachment loads");
+        assert_eq!(bytes.len() as u64, invoice.1.size);
+        assert!(bytes.starts_with(b"%PDF"));
+
+        assert!(client.is_unread(&first[0].id).await.expect("unread check"));
+        client.mark_read(&first[0].id).await.expect("mark read");
+        assert!(!server.unread_ids().contains(&first[0].id));
+        assert!(!client.is_unread(&first[0].id).await.expect("unread check"));
+        assert!(!client.is_unread("missing").await.expect("deleted message is not unread"));
+
+        let reply = first.iter().find(|n| n.id == "msg-004").expect("reply in the same thread");
+        assert_eq!(reply.thread_id, first[0].thread_id);
+        client.mark_thread_read(&reply.thread_id).await.expect("mark thread read");
+        assert!(!server.unread_ids().contains(&reply.id), "threads.modify clears the whole thread");
+
+        let labels = client.labels().await.expect("labels load");
+        assert_eq!(labels.iter().map(|l| l.name.as_str()).collect::<Vec<_>>(), ["Releases", "Счета"]);
+        for action in [
+            MessageAction::Label(labels[0].id.clone()),
+            MessageAction::Star,
+            MessageAction::Important(false),
+            MessageAction::Archive,
+        ] {
+            client.apply("msg-002", &action).await.expect("modify");
+        }
+        assert_eq!(server.labels_of("msg-002"), ["UNREAD", "Label_2", "STARRED"]);
+
+        client.send_reply("msg-004", "Спасибо,\nоплатим завтра").await.expect("reply sent");
+        let [(thread_id, raw)] = server.sent().try_into().expect("one message sent");
+        assert_eq!(thread_id.as_deref(), Some("thread-001"));
+        let (head, text) = raw.split_once("\r\n\r\n").unwrap();
+        let header = |name: &str| {
+            head.split("\r\n")
+                .find_map(|line| line.strip_prefix(&format!("{name}: ")))
+                .unwrap_or_default()
+        };
+        assert_eq!(header("From"), "fake@example.com");
+        assert_eq!(parse_address_list(header("To"))[0].email, "director@example.com");
+        assert_eq!(decode_header(header("Subject")), "Re: Счёт за октябрь");
+        assert_eq!(header("In-Reply-To"), "<reply-1@mail.example.com>");
+        assert_eq!(
+            header("References"),
+            "<invoice-2025-10@billing.example.com> <reply-1@mail.example.com>"
+        );
+        let text = base64.decode(text.replace("\r\n", "")).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), "Спасибо,\r\nоплатим завтра");
+
+        client.apply_to_thread(&reply.thread_id, &[], &MessageAction::Trash).await.expect("trash");
+        assert!(server.labels_of("msg-004").contains(&"TRASH".to_string()));
+
+        // Повторный опрос идёт через историю и не показывает письма снова
+        assert!(client.list_new("in:inbox is:unread", 50).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn transient_failure_keeps_batch_for_next_poll() {
+        let (server, client, _dir) = fake_client(&fixtures()).await;
+
+        server.fail_message("msg-002", true);
+        let err = client.list_new("in:inbox is:unread", 50).await.expect_err("503 fails the poll");
+        assert!(matches!(err.downcast_ref::<GmailApiError>(), Some(GmailApiError::Transient { .. })));
+        assert!(server.unread_ids().iter().all(|id| !client.is_known(id)), "nothing is marked seen");
+
+        server.fail_message("msg-002", false);
+        client.backoff.lock().succeed();
+        let retried = client.list_new("in:inbox is:unread", 50).await.expect("retry succeeds");
+        assert_eq!(retried.len(), server.unread_ids().len(), "every message is shown after the retry");
+    }
+
+    #[tokio::test]
+    async fn history_poll_fetches_only_added_unread_messages() {
+        let fixtures = fixtures();
+        let (server, client, _dir) = fake_client(&fixtures).await;
+        let template: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(fixtures.join("messages/msg-002.json")).unwrap()).unwrap();
+        let message = |id: &str, labels: &[&str]| {
+            let mut message = template.clone();
+            message["id"] = serde_json::json!(id);
+            message["threadId"] = serde_json::json!(format!("thread-{id}"));
+            message["labelIds"] = serde_json::json!(labels);
+            message
+        };
+
+        let capped = client.list_new("in:inbox is:unread", 1).await.expect("capped full sync");
+        assert_eq!(capped.len(), 2);
+        assert_eq!(capped[1].subject, "И ещё 2 непрочитанных писем");
+        assert_eq!(capped[1].url, "https://mail.google.com/mail/u/fake%40example.com/#inbox");
+        // Перезапуск или устаревшая история: те же непрочитанные не дают сводку повторно
+        client.reset_sync();
+        assert!(client.list_new("in:inbox is:unread", 1).await.expect("full resync").is_empty());
+        let lists = server.list_calls();
+
+        server.deliver(message("new-1", &["INBOX", "UNREAD"]));
+        server.deliver(message("sent-1", &["SENT"]));
+        server.deliver(message("spam-1", &["SPAM", "UNREAD"]));
+        server.deliver(message("promo-1", &["CATEGORY_PROMOTIONS", "UNREAD"]));
+        let polled = client.list_new("in:inbox is:unread", 50).await.expect("history poll");
+        assert_eq!(polled.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(), ["new-1"]);
+        assert_eq!(server.list_calls(), lists, "history poll does not re-list the mailbox");
+
+        server.deliver(message("new-2", &["INBOX", "UNREAD"]));
+        server.deliver(message("sent-2", &["SENT"]));
+        server.deliver(message("new-3", &["INBOX", "UNREAD"]));
+        let capped = client.list_new("in:inbox is:unread", 1).await.expect("capped poll");
+        assert_eq!(capped[0].id, "new-3", "the newest message is shown");
+        assert_eq!(capped[1].subject, "И ещё 1 непрочитанных писем", "only matching messages are counted");
+    }
+
+    #[test]
+    fn replaces_cid_references_with_inline_images() {
+        let json = r#"{
+            "mimeType": "multipart/related",
+            "headers": [],
+            "parts": [
+                { "mimeType": "text/html", "body": { "size": 10, "data": "" } },
+                {
+                    "mimeType": "image/png",
+                    "filename": "logo.png",
+                    "headers": [{ "name": "Content-ID", "value": "<logo@example.com>" }],
+                    "body": { "size": 3, "data": "iVBO" }
+                },
+                {
+                    "mimeType": "image/jpeg",
+                    "headers": [{ "name": "Content-Id", "value": "<photo%1>" }],
+                    "body": { "size": 900000, "attachmentId": "att-1" }
+                }
+            ]
+        }"#;
+        let payload: MessagePayload = serde_json::from_str(json).unwrap();
+        assert!(has_text_part(&payload.parts));
+        assert!(!has_text_part(&payload.parts[1..]), "images alone are not a body");
+        let mut parts = Vec::new();
+        collect_inline_images(&payload.parts, &mut parts);
+        let ids: Vec<&str> = parts.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, ["logo@example.com", "photo%1"]);
+
+        let images = HashMap::from([("logo@example.com".to_string(), "data:image/png;base64,iVBO".to_string())]);
+        let html = r#"<img src="cid:logo@example.com"><img src='CID:logo%40example.com'><img src="cid:missing">"#;
+        assert_eq!(
+            replace_cid_references(html, &images),
+            r#"<img src="data:image/png;base64,iVBO"><img src='data:image/png;base64,iVBO'><img src="cid:missing">"#
+        );
+    }
+
+    #[test]
+    fn decodes_body_using_part_charset() {
+        let header = |value: &str| Header {
+            name: "Content-Type".into(),
+            value: value.into(),
+        };
+        // «Привет» в windows-1251 и KOI8-R
+        let cp1251 = MessageBody {
+            data: Some(base64.encode([0xCF, 0xF0, 0xE8, 0xE2, 0xE5, 0xF2]).replace('=', "")),
+            attachment_id: None,
+            size: 6,
+        };
+        let koi8 = MessageBody {
+            data: Some(base64.encode([0xF0, 0xD2, 0xC9, 0xD7, 0xC5, 0xD4])),
+            attachment_id: None,
+            size: 6,
+        };
+        assert_eq!(
+            decode_body(&cp1251, &[header("text/plain; charset=\"windows-1251\"")]).as_deref(),
+            Some("Привет")
+        );
+        assert_eq!(
+            decode_body(&koi8, &[header("text/html; format=flowed; CHARSET=koi8-r")]).as_deref(),
+            Some("Привет")
+        );
+        // Без charset считаем UTF-8 и не теряем тело из-за битых байт
+        assert_eq!(decode_body(&koi8, &[]).map(|text| text.chars().count()), Some(6));
+    }
+
+    #[test]
+    fn classifies_rate_limits_and_server_errors_as_transient() {
+        assert!(is_transient_status(StatusCode::TOO_MANY_REQUESTS, ""));
+        assert!(is_transient_status(StatusCode::SERVICE_UNAVAILABLE, ""));
+        assert!(is_transient_status(
+            StatusCode::FORBIDDEN,
+            r#"{"error":{"errors":[{"reason":"userRateLimitExceeded"}]}}"#
+        ));
+        assert!(!is_transient_status(StatusCode::FORBIDDEN, r#"{"error":{"errors":[{"reason":"forbidden"}]}}"#));
+        assert!(!is_transient_status(StatusCode::BAD_REQUEST, ""));
+    }
+
+    #[test]
+    fn collects_added_and_unread_again_ids_from_history() {
+        let json = r#"
+        {
+            "history": [
+                {
+                    "id": "101",
+                    "messagesAdded": [{ "message": { "id": "m1", "threadId": "t1" } }]
+                },
+                {
+                    "id": "102",
+                    "labelsAdded": [
+                        { "message": { "id": "m2", "threadId": "t2" }, "labelIds": ["UNREAD"] },
+                        { "message": { "id": "m3", "threadId": "t3" }, "labelIds": ["STARRED"] }
+                    ]
+                }
+            ],
+            "historyId": "105"
+        }
+        "#;
+
+        let list: HistoryList = serde_json::from_str(json).expect("history parses");
+        let ids: HashSet<String> = list.added_messages().map(|m| m.id.clone()).collect();
+        assert_eq!(ids, HashSet::from(["m1".to_string(), "m2".to_string()]));
+        assert_eq!(list.history_id.as_deref(), Some("105"));
+        assert!(list.next_page_token.is_none());
+    }
 }
@@ -0,0 +1,283 @@
+//! Разбор заголовков письма: encoded-word (RFC 2047) и списки адресов по RFC 5322.
+//! Gmail API отдаёт значения заголовков как есть, IMAP — сырые заголовки.
+
+use base64::{engine::general_purpose::STANDARD as base64, Engine as _};
+use encoding_rs::Encoding;
+use mail_parser::{Address, MessageParser};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+static ENCODED_WORD: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"=\?([^?\s]+)\?([BbQq])\?([^?\s]*)\?=").unwrap());
+
+/// Декодирует encoded-word (`=?charset?B|Q?...?=`) в значении заголовка.
+///
+/// Пробелы между соседними encoded-word отбрасываются (RFC 2047, 6.2), а байты
+/// соседних слов в одной кодировке склеиваются до перекодирования — почтовики режут
+/// многобайтные символы между словами. Слова с неизвестной кодировкой или битыми
+/// данными остаются как есть.
+pub fn decode_header(value: &str) -> String {
+    let value = unfold(value);
+    if !value.contains("=?") {
+        return value;
+    }
+
+    let mut out = String::with_capacity(value.len());
+    // Кодировка и байты ещё не перекодированных соседних слов
+    let mut pending: Option<(&'static Encoding, Vec<u8>)> = None;
+    let mut last_end = 0;
+    for caps in ENCODED_WORD.captures_iter(&value) {
+        let word = caps.get(0).unwrap();
+        let between = &value[last_end..word.start()];
+        let adjacent = last_end > 0 && between.chars().all(char::is_whitespace) && pending.is_some();
+        let decoded = decode_word(&caps[1], &caps[2], &caps[3]);
+
+        match decoded {
+            Some((encoding, bytes)) => {
+                match pending.as_mut() {
+                    Some((current, buffer)) if adjacent && *current == encoding => buffer.extend(bytes),
+                    _ => {
+                        flush(&mut out, &mut pending);
+                        if !adjacent {
+                            out.push_str(between);
+                        }
+                        pending = Some((encoding, bytes));
+                    }
+                }
+            }
+            None => {
+                flush(&mut out, &mut pending);
+                out.push_str(between);
+                out.push_str(word.as_str());
+            }
+        }
+        last_end = word.end();
+    }
+    flush(&mut out, &mut pending);
+    out.push_str(&value[last_end..]);
+    out
+}
+
+fn flush(out: &mut String, pending: &mut Option<(&'static Encoding, Vec<u8>)>) {
+    if let Some((encoding, bytes)) = pending.take() {
+        out.push_str(&encoding.decode_without_bom_handling(&bytes).0);
+    }
+}
+
+fn decode_word(charset: &str, encoding: &str, text: &str) -> Option<(&'static Encoding, Vec<u8>)> {
+    // RFC 2231: после `*` может идти язык, `=?UTF-8*ru?B?...?=`
+    let charset = charset.split('*').next().unwrap_or(charset);
+    let charset = Encoding::for_label(charset.as_bytes())?;
+    let bytes = if encoding.eq_ignore_ascii_case("B") {
+        let mut padded = text.to_string();
+        while !padded.len().is_multiple_of(4) {
+            padded.push('=');
+        }
+        base64.decode(padded).ok()?
+    } else {
+        decode_q(text)?
+    };
+    Some((charset, bytes))
+}
+
+fn decode_q(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.bytes();
+    while let Some(byte) = chars.next() {
+        match byte {
+            b'_' => bytes.push(b' '),
+            b'=' => {
+                let hex = [chars.next()?, chars.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    Some(bytes)
+}
+
+/// Склеивает перенесённые строки заголовка (CRLF + пробел).
+fn unfold(value: &str) -> String {
+    if !value.contains(['\r', '\n']) {
+        return value.to_string();
+    }
+    value
+        .split(['\r', '\n'])
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(i, line)| if i == 0 { line } else { line.trim_start_matches([' ', '\t']) })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmailAddress {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub email: String,
+}
+
+impl EmailAddress {
+    /// `Имя <email>` или просто адрес, если имени нет.
+    pub fn display(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{name} <{}>", self.email),
+            None => self.email.clone(),
+        }
+    }
+
+    /// Адрес для заголовка исходящего письма: имя в кавычках или encoded-word.
+    pub fn to_header(&self) -> String {
+        match &self.name {
+            Some(name) if !name.is_ascii() => format!("{} <{}>", encode_header(name), self.email),
+            Some(name) => {
+                let quoted = name.replace('\\', "\\\\").replace('"', "\\\"");
+                format!("\"{quoted}\" <{}>", self.email)
+            }
+            None => self.email.clone(),
+        }
+    }
+}
+
+/// Значение заголовка исходящего письма: ASCII как есть, иначе encoded-word
+/// в UTF-8. Слова режутся по символам, чтобы каждое укладывалось в 75 знаков.
+pub fn encode_header(value: &str) -> String {
+    let value = value.replace(['\r', '\n'], " ");
+    if value.is_ascii() {
+        return value;
+    }
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in value.chars() {
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(std::mem::take(&mut chunk));
+        }
+        chunk.push(c);
+    }
+    words.push(chunk);
+    words
+        .iter()
+        .map(|word| format!("=?UTF-8?B?{}?=", base64.encode(word)))
+        .collect::<Vec<_>>()
+        .join("\r\n ")
+}
+
+/// Адреса из значения заголовка `To`/`Cc`/`From`/`Reply-To`, включая группы.
+/// Записи без адреса (пустые группы, `undisclosed-recipients:;`) пропускаются.
+pub fn parse_address_list(value: &str) -> Vec<EmailAddress> {
+    let raw = format!("To: {}\r\n\r\n", value.replace(['\r', '\n'], " "));
+    MessageParser::default()
+        .parse_headers(raw.as_bytes())
+        .and_then(|message| message.to().map(addresses))
+        .unwrap_or_default()
+}
+
+/// Адреса из уже разобранного mail-parser заголовка (IMAP-бэкенд).
+pub fn addresses(address: &Address<'_>) -> Vec<EmailAddress> {
+    address
+        .iter()
+        .filter_map(|addr| {
+            let email = addr.address.as_deref()?.trim();
+            if email.is_empty() {
+                return None;
+            }
+            let name = addr
+                .name
+                .as_deref()
+                .map(str::trim)
+                .filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case(email))
+                .map(str::to_string);
+            Some(EmailAddress {
+                name,
+                email: email.to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn join_display(addresses: &[EmailAddress]) -> Option<String> {
+    (!addresses.is_empty()).then(|| {
+        addresses
+            .iter()
+            .map(EmailAddress::display)
+            .collect::<Vec<_>>()
+            .join(", ")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_encoded_words() {
+        // B и Q, разные кодировки в одном заголовке
+        assert_eq!(decode_header("=?KOI8-R?B?8NLJ18XU?="), "Привет");
+        assert_eq!(decode_header("Re: =?windows-1251?Q?=D1=F7=B8=F2_=E7=E0?= октябрь"), "Re: Счёт за октябрь");
+        assert_eq!(
+            decode_header("=?UTF-8?B?0J/RgNC40LLQtdGC?= =?ISO-8859-1?Q?caf=E9?= !"),
+            "Приветcafé !"
+        );
+        // Пробел между словами исчезает, между словом и текстом — остаётся
+        assert_eq!(decode_header("=?UTF-8?Q?a?=  \r\n =?UTF-8?Q?b?= c"), "ab c");
+        // Многобайтный символ разрезан между двумя словами
+        assert_eq!(decode_header("=?UTF-8?B?0J/R?= =?UTF-8?B?gNC4?="), "При");
+        // Язык по RFC 2231, регистр кодировки и отсутствие паддинга
+        assert_eq!(decode_header("=?utf-8*ru?b?0JTQsA?="), "Да");
+        // Неизвестная кодировка и битые данные не трогаем
+        assert_eq!(decode_header("=?x-unknown?Q?abc?= ok"), "=?x-unknown?Q?abc?= ok");
+        assert_eq!(decode_header("=?UTF-8?Q?bad=Z?="), "=?UTF-8?Q?bad=Z?=");
+        assert_eq!(decode_header("Обычная тема"), "Обычная тема");
+    }
+
+    #[test]
+    fn encodes_outgoing_headers() {
+        assert_eq!(encode_header("Re: plain\r\nBcc: x"), "Re: plain  Bcc: x");
+        let subject = "Re: Счёт за октябрь и акт сверки взаиморасчётов за третий квартал";
+        let encoded = encode_header(subject);
+        assert!(encoded.split("\r\n ").all(|word| word.len() <= 75 && word.is_ascii()));
+        assert_eq!(decode_header(&encoded), subject);
+
+        let quoted = EmailAddress {
+            name: Some("Doe, \"JD\" John".into()),
+            email: "john@example.com".into(),
+        };
+        assert_eq!(quoted.to_header(), "\"Doe, \\\"JD\\\" John\" <john@example.com>");
+        let cyrillic = EmailAddress {
+            name: Some("Иван Петров".into()),
+            email: "ivan@example.com".into(),
+        };
+        assert_eq!(parse_address_list(&cyrillic.to_header()), vec![cyrillic]);
+    }
+
+    #[test]
+    fn parses_address_lists_with_encoded_names() {
+        let parsed = parse_address_list(
+            "=?UTF-8?B?0JjQstCw0L0g0J/QtdGC0YDQvtCy?= <ivan@example.com>, \"Doe, John\" <john@example.com>,\r\n plain@example.com",
+        );
+        assert_eq!(
+            parsed,
+            vec![
+                EmailAddress {
+                    name: Some("Иван Петров".into()),
+                    email: "ivan@example.com".into()
+                },
+                EmailAddress {
+                    name: Some("Doe, John".into()),
+                    email: "john@example.com".into()
+                },
+                EmailAddress {
+                    name: None,
+                    email: "plain@example.com".into()
+                },
+            ]
+        );
+
+        let group = parse_address_list("Team: =?KOI8-R?Q?=F0=C5=D4=D2?= <petr@example.com>, anna@example.com;, undisclosed-recipients:;");
+        let emails: Vec<&str> = group.iter().map(|a| a.email.as_str()).collect();
+        assert_eq!(emails, ["petr@example.com", "anna@example.com"]);
+        assert_eq!(group[0].name.as_deref(), Some("Петр"));
+        assert!(parse_address_list("").is_empty());
+    }
+}
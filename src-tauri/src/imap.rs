@@ -0,0 +1,785 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mail_parser::{MessageParser, MimeHeaders};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex as AsyncMutex, MutexGuard};
+use tokio::time::timeout;
+use tokio_rustls::rustls::{self, pki_types::ServerName, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, info, warn};
+
+use crate::config::{ImapSecurity, ImapSettings, SettingsManager};
+use crate::gmail::{Attachment, GmailNotification};
+use crate::headers::{addresses, join_display};
+use crate::oauth::SERVICE_NAME;
+use crate::provider::{MailBody, MailProvider, MessageAction};
+use crate::seen::{NotificationOutcome, SeenStore};
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+// RFC 2177: сервер вправе разорвать IDLE через 30 минут, перезапускаем раньше
+const IDLE_RESTART: Duration = Duration::from_secs(25 * 60);
+const FETCH_BYTES_LIMIT: usize = 256 * 1024;
+// Сколько писем запрашивать одной командой FETCH, чтобы не тянуть весь лимит разом
+const FETCH_CHUNK: usize = 10;
+const SNIPPET_CHARS: usize = 200;
+
+/// IMAP-бэкенд: `UID SEARCH UNSEEN` при опросе и IDLE для мгновенных уведомлений.
+pub struct ImapClient {
+    settings: Arc<SettingsManager>,
+    seen: Arc<SeenStore>,
+    session: AsyncMutex<Option<Connection>>,
+    idle: AsyncMutex<Option<Connection>>,
+}
+
+impl ImapClient {
+    pub fn new(settings: Arc<SettingsManager>, seen: Arc<SeenStore>) -> Self {
+        Self {
+            settings,
+            seen,
+            session: AsyncMutex::new(None),
+            idle: AsyncMutex::new(None),
+        }
+    }
+
+    async fn connect(&self) -> Result<Connection> {
+        let imap = self.settings.get().imap;
+        if imap.host.trim().is_empty() || imap.username.trim().is_empty() {
+            bail!("IMAP server is not configured");
+        }
+        let password = load_password(&imap)?;
+        Connection::open(&imap, &password).await
+    }
+
+    /// Командная сессия переиспользуется между опросами и пересоздаётся после ошибки.
+    async fn connection(&self) -> Result<MutexGuard<'_, Option<Connection>>> {
+        let mut guard = self.session.lock().await;
+        if guard.is_none() {
+            *guard = Some(self.connect().await?);
+        }
+        Ok(guard)
+    }
+
+    async fn list_new_on(&self, conn: &mut Connection, limit: usize) -> Result<Vec<GmailNotification>> {
+        let imap = self.settings.get().imap;
+        let uid_validity = conn.select(&imap.mailbox).await?;
+        let mut uids = conn.search_unseen().await?;
+        uids.retain(|uid| !self.seen.is_known(&message_id(uid_validity, *uid)));
+        uids.sort_unstable();
+        debug!(count = uids.len(), "imap: новые непрочитанные письма");
+
+        // Берём самые свежие письма, остальные попадут в сводку
+        let overflow = uids.len().saturating_sub(limit.max(1));
+        let fresh = &uids[overflow..];
+        if fresh.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let web_url = imap.web_url.unwrap_or_default();
+        let mut notifications = Vec::new();
+        for chunk in fresh.chunks(FETCH_CHUNK) {
+            for message in conn.fetch(chunk).await? {
+                let id = message_id(uid_validity, message.uid);
+                match message.into_notification(&id, &imap.username, &web_url) {
+                    Some(notification) => notifications.push(notification),
+                    None => warn!(message_id = %id, "imap: не удалось разобрать письмо"),
+                }
+            }
+        }
+        // Отмечаем только после успешной загрузки всех частей; не влезшие в лимит
+        // уже учтены в сводке и не должны попасть в неё на следующем опросе
+        let overflow_ids = uids[..overflow].iter().map(|uid| message_id(uid_validity, *uid));
+        self.seen
+            .mark_seen_many(notifications.iter().map(|n| n.id.clone()).chain(overflow_ids));
+        notifications.sort_by_key(|n| n.received_at);
+        if overflow > 0 {
+            info!(overflow, limit, "imap: достигнут лимит писем за опрос");
+            notifications.push(GmailNotification::overflow_summary(
+                &imap.username,
+                overflow,
+                &web_url,
+            ));
+        }
+        Ok(notifications)
+    }
+
+    async fn fetch_on(&self, conn: &mut Connection, id: &str) -> Result<FetchedMessage> {
+        let imap = self.settings.get().imap;
+        let (uid_validity, uid) = parse_message_id(id)?;
+        ensure_uid_validity(conn.select(&imap.mailbox).await?, uid_validity)?;
+        conn.fetch(&[uid])
+            .await?
+            .into_iter()
+            .next()
+            .with_context(|| format!("imap message {id} not found"))
+    }
+
+    /// Письмо целиком, без ограничения `FETCH_BYTES_LIMIT` — для тела и вложений.
+    async fn fetch_full_on(&self, conn: &mut Connection, id: &str) -> Result<FetchedMessage> {
+        let imap = self.settings.get().imap;
+        let (uid_validity, uid) = parse_message_id(id)?;
+        ensure_uid_validity(conn.select(&imap.mailbox).await?, uid_validity)?;
+        let lines = conn
+            .command(&format!("UID FETCH {uid} (UID INTERNALDATE BODY.PEEK[])"))
+            .await?;
+        lines
+            .into_iter()
+            .find_map(FetchedMessage::parse)
+            .with_context(|| format!("imap message {id} not found"))
+    }
+
+    async fn is_unread_on(&self, conn: &mut Connection, id: &str) -> Result<bool> {
+        let imap = self.settings.get().imap;
+        let (uid_validity, uid) = parse_message_id(id)?;
+        ensure_uid_validity(conn.select(&imap.mailbox).await?, uid_validity)?;
+        let lines = conn.command(&format!("UID FETCH {uid} (UID FLAGS)")).await?;
+        // Без ответа FETCH письма в ящике уже нет: удалено или перемещено
+        Ok(lines
+            .iter()
+            .find(|line| line.text.starts_with("* ") && response_uid(&line.text) == Some(uid))
+            .is_some_and(|line| !line.text.contains("\\Seen")))
+    }
+
+    async fn add_flag_on(&self, conn: &mut Connection, id: &str, flag: &str) -> Result<()> {
+        let imap = self.settings.get().imap;
+        let (uid_validity, uid) = parse_message_id(id)?;
+        ensure_uid_validity(conn.select(&imap.mailbox).await?, uid_validity)?;
+        conn.command(&format!("UID STORE {uid} +FLAGS.SILENT ({flag})"))
+            .await?;
+        Ok(())
+    }
+
+    async fn add_flag(&self, id: &str, flag: &str) -> Result<()> {
+        let mut guard = self.connection().await?;
+        let conn = guard.as_mut().context("imap session is missing")?;
+        let result = self.add_flag_on(conn, id, flag).await;
+        if result.is_err() {
+            *guard = None;
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl MailProvider for ImapClient {
+    async fn list_new(&self, _query: &str, limit: usize) -> Result<Vec<GmailNotification>> {
+        // Поисковый запрос Gmail к IMAP неприменим: смотрим все непрочитанные в ящике
+        let mut guard = self.connection().await?;
+        let conn = guard.as_mut().context("imap session is missing")?;
+        let result = self.list_new_on(conn, limit).await;
+        if result.is_err() {
+            *guard = None;
+        }
+        result
+    }
+
+    async fn fetch(&self, id: &str) -> Result<GmailNotification> {
+        let mut guard = self.connection().await?;
+        let conn = guard.as_mut().context("imap session is missing")?;
+        let result = self.fetch_on(conn, id).await;
+        if result.is_err() {
+            *guard = None;
+        }
+        let imap = self.settings.get().imap;
+        result?
+            .into_notification(id, &imap.username, &imap.web_url.unwrap_or_default())
+            .with_context(|| format!("imap message {id} is not parseable"))
+    }
+
+    async fn body(&self, id: &str) -> Result<Option<MailBody>> {
+        let mut guard = self.connection().await?;
+        let conn = guard.as_mut().context("imap session is missing")?;
+        // Обрезанное по FETCH_BYTES_LIMIT письмо обрывается посреди MIME-части
+        let result = self.fetch_full_on(conn, id).await;
+        if result.is_err() {
+            *guard = None;
+        }
+        Ok(result?.body())
+    }
+
+    async fn attachment(&self, message_id: &str, attachment_id: &str) -> Result<Vec<u8>> {
+        let index: usize = attachment_id
+            .parse()
+            .with_context(|| format!("invalid imap attachment id {attachment_id}"))?;
+        let mut guard = self.connection().await?;
+        let conn = guard.as_mut().context("imap session is missing")?;
+        let result = self.fetch_full_on(conn, message_id).await;
+        if result.is_err() {
+            *guard = None;
+        }
+        let fetched = result?;
+        let message = MessageParser::default()
+            .parse(&fetched.raw)
+            .with_context(|| format!("imap message {message_id} is not parseable"))?;
+        let contents = message.attachments().nth(index).map(|part| part.contents().to_vec());
+        contents.with_context(|| format!("imap message {message_id} has no attachment {attachment_id}"))
+    }
+
+    async fn is_unread(&self, id: &str) -> Result<bool> {
+        let mut guard = self.connection().await?;
+        let conn = guard.as_mut().context("imap session is missing")?;
+        let result = self.is_unread_on(conn, id).await;
+        if result.is_err() {
+            *guard = None;
+        }
+        result
+    }
+
+    async fn mark_read(&self, id: &str) -> Result<()> {
+        self.add_flag(id, "\\Seen").await
+    }
+
+    /// Из действий IMAP умеет только звёздочку: у папок нет общих меток
+    /// «Входящие»/«Важное», а корзина называется на каждом сервере по-своему.
+    async fn apply(&self, id: &str, action: &MessageAction) -> Result<()> {
+        match action {
+            MessageAction::Star => self.add_flag(id, "\\Flagged").await,
+            _ => bail!("{action:?} is not supported by the IMAP backend"),
+        }
+    }
+
+    fn record_outcome(&self, id: &str, outcome: NotificationOutcome) {
+        self.seen.record_outcome(id, outcome);
+    }
+
+    fn forget(&self, id: &str) {
+        self.seen.forget(id);
+    }
+
+    fn reset_sync(&self) {
+        // UNSEEN ищется целиком на каждом опросе, точки синхронизации нет
+    }
+
+    /// Без IDLE (выключен в настройках или не поддерживается сервером) не завершается
+    /// никогда: письма приходят только по опросу, а включённый позже IDLE заработает
+    /// после перезапуска.
+    async fn wait_for_change(&self) -> Result<()> {
+        let imap = self.settings.get().imap;
+        if !imap.use_idle {
+            return std::future::pending().await;
+        }
+
+        // Для IDLE держим отдельное соединение, чтобы не блокировать команды опроса
+        let mut guard = self.idle.lock().await;
+        if guard.is_none() {
+            let mut conn = self.connect().await?;
+            if !conn.has_capability("IDLE") {
+                warn!("imap: сервер не поддерживает IDLE, остаёмся на опросе");
+                drop(guard);
+                return std::future::pending().await;
+            }
+            conn.select(&imap.mailbox).await?;
+            *guard = Some(conn);
+        }
+        let conn = guard.as_mut().context("imap idle session is missing")?;
+        let result = loop {
+            match conn.idle().await {
+                Ok(true) => break Ok(()),
+                Ok(false) => debug!("imap: перезапуск IDLE по таймеру"),
+                Err(err) => break Err(err),
+            }
+        };
+        if result.is_err() {
+            *guard = None;
+        }
+        result
+    }
+}
+
+pub fn store_password(settings: &ImapSettings, password: &str) -> Result<()> {
+    keyring_entry(settings)?
+        .set_password(password)
+        .context("failed to store IMAP password")
+}
+
+fn load_password(settings: &ImapSettings) -> Result<String> {
+    keyring_entry(settings)?
+        .get_password()
+        .context("IMAP password is not set")
+}
+
+fn keyring_entry(settings: &ImapSettings) -> Result<keyring::Entry> {
+    let user = format!("imap:{}@{}", settings.username, settings.host);
+    keyring::Entry::new(SERVICE_NAME, &user).context("failed to open keyring entry")
+}
+
+fn message_id(uid_validity: u32, uid: u32) -> String {
+    format!("imap:{uid_validity}:{uid}")
+}
+
+fn parse_message_id(id: &str) -> Result<(u32, u32)> {
+    let mut parts = id.strip_prefix("imap:").unwrap_or(id).split(':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(validity), Some(uid), None) => Ok((
+            validity.parse().context("invalid UIDVALIDITY in message id")?,
+            uid.parse().context("invalid UID in message id")?,
+        )),
+        _ => bail!("invalid imap message id {id}"),
+    }
+}
+
+fn ensure_uid_validity(current: u32, expected: u32) -> Result<()> {
+    if current != expected {
+        bail!("mailbox UIDVALIDITY changed ({expected} -> {current})");
+    }
+    Ok(())
+}
+
+trait ImapIo: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ImapIo for T {}
+
+/// Строка ответа сервера; литералы `{N}` вынесены в `literals` в порядке появления.
+#[derive(Debug, Default)]
+struct ResponseLine {
+    text: String,
+    literals: Vec<Vec<u8>>,
+}
+
+struct Connection {
+    stream: BufReader<Box<dyn ImapIo>>,
+    next_tag: u32,
+    capabilities: Vec<String>,
+}
+
+impl Connection {
+    async fn open(settings: &ImapSettings, password: &str) -> Result<Self> {
+        debug!(host = %settings.host, port = settings.port, security = ?settings.security, "imap: connect");
+        let tcp = timeout(
+            COMMAND_TIMEOUT,
+            TcpStream::connect((settings.host.as_str(), settings.port)),
+        )
+        .await
+        .context("imap connect timed out")?
+        .context("failed to connect to imap server")?;
+
+        let io: Box<dyn ImapIo> = match settings.security {
+            ImapSecurity::Tls => Box::new(tls_connect(&settings.host, tcp).await?),
+            ImapSecurity::Plain => {
+                warn!(host = %settings.host, "imap: соединение без шифрования, пароль уйдёт открытым текстом");
+                Box::new(tcp)
+            }
+            ImapSecurity::StartTls => {
+                let mut plain = BufReader::new(tcp);
+                expect_greeting(&mut plain).await?;
+                plain.get_mut().write_all(b"S0 STARTTLS\r\n").await?;
+                loop {
+                    let line = read_response(&mut plain).await?;
+                    if let Some(status) = line.text.strip_prefix("S0 ") {
+                        if !status.starts_with("OK") {
+                            bail!("imap STARTTLS rejected: {status}");
+                        }
+                        break;
+                    }
+                }
+                Box::new(tls_connect(&settings.host, plain.into_inner()).await?)
+            }
+        };
+
+        let mut conn = Self {
+            stream: BufReader::new(io),
+            next_tag: 0,
+            capabilities: Vec::new(),
+        };
+        if settings.security != ImapSecurity::StartTls {
+            expect_greeting(&mut conn.stream).await?;
+        }
+        conn.command(&format!(
+            "LOGIN {} {}",
+            quote(&settings.username),
+            quote(password)
+        ))
+        .await
+        .context("imap login failed")?;
+        // Возможности после входа могут отличаться от объявленных до него
+        for line in conn.command("CAPABILITY").await? {
+            if let Some(list) = line.text.strip_prefix("* CAPABILITY ") {
+                conn.capabilities = list.split_whitespace().map(str::to_ascii_uppercase).collect();
+            }
+        }
+        Ok(conn)
+    }
+
+    fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|cap| cap == name)
+    }
+
+    async fn send(&mut self, command: &str) -> Result<String> {
+        self.next_tag += 1;
+        let tag = format!("A{}", self.next_tag);
+        let verb = command.split_whitespace().next().unwrap_or_default();
+        debug!(%tag, %verb, "imap: command");
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{tag} {command}\r\n").as_bytes())
+            .await
+            .context("failed to send imap command")?;
+        stream.flush().await?;
+        Ok(tag)
+    }
+
+    async fn read_until_tagged(&mut self, tag: &str) -> Result<Vec<ResponseLine>> {
+        let mut untagged = Vec::new();
+        loop {
+            let line = read_response(&mut self.stream).await?;
+            if let Some(status) = line.text.strip_prefix(tag).and_then(|rest| rest.strip_prefix(' ')) {
+                if status.starts_with("OK") {
+                    return Ok(untagged);
+                }
+                bail!("imap command failed: {status}");
+            }
+            untagged.push(line);
+        }
+    }
+
+    async fn command(&mut self, command: &str) -> Result<Vec<ResponseLine>> {
+        let tag = self.send(command).await?;
+        timeout(COMMAND_TIMEOUT, self.read_until_tagged(&tag))
+            .await
+            .context("imap command timed out")?
+    }
+
+    /// Возвращает UIDVALIDITY выбранного ящика.
+    async fn select(&mut self, mailbox: &str) -> Result<u32> {
+        let lines = self.command(&format!("SELECT {}", quote(mailbox))).await?;
+        lines
+            .iter()
+            .find_map(|line| {
+                let rest = line.text.split("[UIDVALIDITY ").nth(1)?;
+                rest.split(']').next()?.trim().parse().ok()
+            })
+            .ok_or_else(|| anyhow!("server did not report UIDVALIDITY for {mailbox}"))
+    }
+
+    async fn search_unseen(&mut self) -> Result<Vec<u32>> {
+        let lines = self.command("UID SEARCH UNSEEN").await?;
+        Ok(lines
+            .iter()
+            .filter_map(|line| line.text.strip_prefix("* SEARCH"))
+            .flat_map(|rest| rest.split_whitespace().filter_map(|uid| uid.parse().ok()))
+            .collect())
+    }
+
+    async fn fetch(&mut self, uids: &[u32]) -> Result<Vec<FetchedMessage>> {
+        let set = uids
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        let lines = self
+            .command(&format!(
+                "UID FETCH {set} (UID INTERNALDATE BODY.PEEK[]<0.{FETCH_BYTES_LIMIT}>)"
+            ))
+            .await?;
+        Ok(lines.into_iter().filter_map(FetchedMessage::parse).collect())
+    }
+
+    /// Ждёт изменений в ящике. `false` — истёк таймер и IDLE нужно перезапустить.
+    async fn idle(&mut self) -> Result<bool> {
+        let tag = self.send("IDLE").await?;
+        let continuation = timeout(COMMAND_TIMEOUT, read_response(&mut self.stream))
+            .await
+            .context("imap IDLE was not acknowledged")??;
+        if !continuation.text.starts_with('+') {
+            bail!("imap IDLE rejected: {}", continuation.text);
+        }
+
+        let changed = loop {
+            match timeout(IDLE_RESTART, read_response(&mut self.stream)).await {
+                Ok(line) => {
+                    let line = line?;
+                    if is_mailbox_change(&line.text) {
+                        debug!(response = %line.text, "imap: изменения в ящике");
+                        break true;
+                    }
+                }
+                Err(_) => break false,
+            }
+        };
+
+        let stream = self.stream.get_mut();
+        stream.write_all(b"DONE\r\n").await?;
+        stream.flush().await?;
+        timeout(COMMAND_TIMEOUT, self.read_until_tagged(&tag))
+            .await
+            .context("imap IDLE termination timed out")??;
+        Ok(changed)
+    }
+}
+
+async fn tls_connect(host: &str, tcp: TcpStream) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    let mut roots = RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    for err in &native.errors {
+        warn!(%err, "imap: failed to load a native certificate");
+    }
+    let (added, _) = roots.add_parsable_certificates(native.certs);
+    debug!(added, "imap: native root certificates loaded");
+
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .context("invalid TLS configuration")?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    let server_name = ServerName::try_from(host.to_string()).context("invalid IMAP host name")?;
+    TlsConnector::from(Arc::new(config))
+        .connect(server_name, tcp)
+        .await
+        .context("imap TLS handshake failed")
+}
+
+async fn expect_greeting<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<()> {
+    let greeting = timeout(COMMAND_TIMEOUT, read_response(reader))
+        .await
+        .context("imap greeting timed out")??;
+    if !greeting.text.starts_with("* OK") {
+        bail!("unexpected imap greeting: {}", greeting.text);
+    }
+    Ok(())
+}
+
+async fn read_response<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<ResponseLine> {
+    let mut line = ResponseLine::default();
+    loop {
+        let mut buf = Vec::new();
+        let read = reader
+            .read_until(b'\n', &mut buf)
+            .await
+            .context("failed to read imap response")?;
+        if read == 0 {
+            bail!("imap connection closed by server");
+        }
+        let chunk = String::from_utf8_lossy(&buf);
+        let chunk = chunk.trim_end_matches(['\r', '\n']);
+        line.text.push_str(chunk);
+        match literal_len(chunk) {
+            Some(len) => {
+                let mut data = vec![0; len];
+                reader
+                    .read_exact(&mut data)
+                    .await
+                    .context("failed to read imap literal")?;
+                line.literals.push(data);
+            }
+            None => return Ok(line),
+        }
+    }
+}
+
+fn literal_len(chunk: &str) -> Option<usize> {
+    let rest = chunk.strip_suffix('}')?;
+    let start = rest.rfind('{')?;
+    rest[start + 1..].trim_end_matches('+').parse().ok()
+}
+
+fn is_mailbox_change(text: &str) -> bool {
+    text.starts_with("* ") && (text.ends_with(" EXISTS") || text.ends_with(" RECENT"))
+}
+
+/// UID из строки ответа `* N FETCH (UID 42 ...)`.
+fn response_uid(text: &str) -> Option<u32> {
+    if !text.contains(" FETCH (") {
+        return None;
+    }
+    text.split("UID ")
+        .nth(1)?
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+struct FetchedMessage {
+    uid: u32,
+    internal_date: Option<DateTime<Utc>>,
+    raw: Vec<u8>,
+}
+
+impl FetchedMessage {
+    fn parse(mut line: ResponseLine) -> Option<Self> {
+        if !line.text.starts_with("* ") || !line.text.contains(" FETCH (") {
+            return None;
+        }
+        let uid = response_uid(&line.text)?;
+        let internal_date = line
+            .text
+            .split("INTERNALDATE \"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .and_then(|value| DateTime::parse_from_str(value.trim(), "%d-%b-%Y %H:%M:%S %z").ok())
+            .map(|dt| dt.with_timezone(&Utc));
+        if line.literals.is_empty() {
+            return None;
+        }
+        let raw = line.literals.swap_remove(0);
+        Some(Self {
+            uid,
+            internal_date,
+            raw,
+        })
+    }
+
+    /// HTML-версия письма, если её нет — текстовая. `body_html` сам превращает
+    /// текст в HTML, поэтому тип части проверяем явно.
+    fn body(&self) -> Option<MailBody> {
+        let message = MessageParser::default().parse(&self.raw)?;
+        match message.html_part(0).filter(|part| part.is_text_html()) {
+            Some(part) => part.text_contents().map(|html| MailBody::Html(html.to_string())),
+            None => message.body_text(0).map(|text| MailBody::Text(text.into_owned())),
+        }
+    }
+
+    fn into_notification(self, id: &str, account: &str, web_url: &str) -> Option<GmailNotification> {
+        let message = MessageParser::default().parse(&self.raw)?;
+        let text = message.body_text(0);
+        let snippet = text.as_ref().map(|text| {
+            text.split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .chars()
+                .take(SNIPPET_CHARS)
+                .collect::<String>()
+        });
+        let has_body = message.body_html(0).is_some() || text.is_some();
+        // Идентификатор вложения — его порядковый номер в письме.
+        // Размер у обрезанных по FETCH_BYTES_LIMIT писем может быть занижен.
+        let attachments = message
+            .attachments()
+            .enumerate()
+            .filter_map(|(index, part)| {
+                let filename = part.attachment_name()?.to_string();
+                let mime_type = part
+                    .content_type()
+                    .map(|ct| match ct.c_subtype.as_deref() {
+                        Some(subtype) => format!("{}/{}", ct.c_type, subtype),
+                        None => ct.c_type.to_string(),
+                    })
+                    .unwrap_or_else(|| "application/octet-stream".to_string());
+                Some(Attachment {
+                    id: index.to_string(),
+                    filename,
+                    mime_type,
+                    size: part.contents().len() as u64,
+                })
+            })
+            .collect();
+        let references = message.references();
+        let thread_id = references
+            .as_text_list()
+            .and_then(|list| list.first().map(|id| id.to_string()))
+            .or_else(|| references.as_text().map(str::to_string))
+            .or_else(|| message.message_id().map(str::to_string))
+            .unwrap_or_else(|| id.to_string());
+        let header_date = message
+            .date()
+            .and_then(|date| DateTime::from_timestamp(date.to_timestamp(), 0));
+
+        let from = message.from().map(addresses).unwrap_or_default();
+        let to = message.to().map(addresses).unwrap_or_default();
+
+        Some(GmailNotification {
+            id: id.to_string(),
+            account: account.to_string(),
+            thread_id,
+            subject: message
+                .subject()
+                .map(str::to_string)
+                .unwrap_or_else(|| "(без темы)".to_string()),
+            snippet,
+            sender: join_display(&from),
+            recipient: join_display(&to),
+            from: from.into_iter().next(),
+            to,
+            cc: message.cc().map(addresses).unwrap_or_default(),
+            reply_to: message.reply_to().map(addresses).unwrap_or_default(),
+            received_at: self.internal_date.or(header_date),
+            header_date,
+            internal_date: self.internal_date,
+            received_local: None,
+            received_relative: None,
+            url: web_url.to_string(),
+            attachments,
+            merged_ids: Vec::new(),
+            labels: Vec::new(),
+            sound_path: None,
+            priority: false,
+            vip: false,
+            silent: false,
+            has_body,
+            // См. `apply`: IMAP умеет только звёздочку
+            mailbox_actions: false,
+            summary: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn parses_fetch_response_with_literal() {
+        let raw = "Subject: =?UTF-8?B?0J/RgNC40LLQtdGC?=\r\nFrom: Sender <sender@example.com>\r\nTo: me@example.com\r\nMessage-ID: <m1@example.com>\r\n\r\nHello there\r\n";
+        let wire = format!(
+            "* 3 FETCH (UID 42 INTERNALDATE \"24-Oct-2024 15:30:00 +0000\" BODY[]<0> {{{}}}\r\n{})\r\n",
+            raw.len(),
+            raw
+        );
+        let mut reader = BufReader::new(wire.as_bytes());
+        let line = read_response(&mut reader).await.expect("response parses");
+        assert_eq!(line.literals.len(), 1);
+
+        let fetched = FetchedMessage::parse(line).expect("fetch parses");
+        assert_eq!(fetched.uid, 42);
+        assert!(fetched.internal_date.is_some());
+        assert!(matches!(fetched.body(), Some(MailBody::Text(text)) if text.contains("Hello there")));
+
+        let notification = fetched
+            .into_notification("imap:7:42", "me@example.com", "")
+            .expect("message parses");
+        assert_eq!(notification.subject, "Привет");
+        assert_eq!(notification.sender.as_deref(), Some("Sender <sender@example.com>"));
+        assert_eq!(notification.thread_id, "m1@example.com");
+        assert_eq!(notification.snippet.as_deref(), Some("Hello there"));
+        assert_eq!(parse_message_id(&notification.id).unwrap(), (7, 42));
+    }
+
+    /// Проверка против живого сервера, например Dovecot в контейнере:
+    /// `IMAP_TEST_HOST=localhost IMAP_TEST_PORT=143 IMAP_TEST_USER=... IMAP_TEST_PASSWORD=...
+    /// cargo test imap -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn talks_to_live_server() {
+        let env = |name: &str| std::env::var(name).unwrap_or_default();
+        let settings = ImapSettings {
+            host: env("IMAP_TEST_HOST"),
+            port: env("IMAP_TEST_PORT").parse().unwrap_or(143),
+            security: match env("IMAP_TEST_SECURITY").as_str() {
+                "tls" => ImapSecurity::Tls,
+                "starttls" => ImapSecurity::StartTls,
+                _ => ImapSecurity::Plain,
+            },
+            username: env("IMAP_TEST_USER"),
+            ..ImapSettings::default()
+        };
+        let mut conn = Connection::open(&settings, &env("IMAP_TEST_PASSWORD"))
+            .await
+            .expect("login succeeds");
+        conn.select(&settings.mailbox).await.expect("select succeeds");
+        let unseen = conn.search_unseen().await.expect("search succeeds");
+        if let Some(uid) = unseen.first() {
+            let fetched = conn.fetch(&[*uid]).await.expect("fetch succeeds");
+            assert_eq!(fetched.len(), 1);
+        }
+    }
+}
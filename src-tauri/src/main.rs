@@ -1,34 +1,55 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod backoff;
 mod config;
+#[cfg(test)]
+mod fake_gmail;
 mod gmail;
+mod headers;
+mod imap;
 mod notifier;
 mod oauth;
+mod provider;
+mod rules;
+mod sanitize;
+mod schedule;
+mod seen;
+mod snooze;
+mod templates;
 
 use std::fs;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use parking_lot::Mutex;
+use parking_lot::RwLock;
 
 use anyhow::Result;
-use config::{Settings, SettingsManager, SettingsUpdate};
-use gmail::{wait_for_authorisation, GmailClient, GmailNotification};
+use config::{MailBackend, Settings, SettingsManager, SettingsUpdate};
+use gmail::{is_summary_id, wait_for_authorisation, GmailApiError, GmailClient, GmailNotification};
+use imap::ImapClient;
 use notifier::NotificationQueue;
-use oauth::{ensure_autostart, AccessTokenProvider, OAuthController, OAuthError};
+use oauth::{ensure_autostart, OAuthController, OAuthError};
+use provider::{MailBody, MailLabel, MailProvider, MessageAction};
+use rules::{Rule, RuleAction, RuleSet};
+use sanitize::{sanitise_html, sanitise_text, SanitisedBody};
+use seen::{NotificationOutcome, SeenStore};
+use snooze::{SnoozeStore, SnoozeTarget};
+use templates::ReplyTemplate;
 use serde::Serialize;
 use serde_json;
 use tauri::WindowEvent;
 use tauri::{
     image::Image,
-    menu::{MenuBuilder, MenuItem},
+    menu::{MenuBuilder, MenuItem, SubmenuBuilder},
     path::BaseDirectory,
     tray::{MouseButton, TrayIconBuilder, TrayIconEvent},
     AppHandle, Emitter, Manager,
 };
 use tauri_plugin_autostart::MacosLauncher;
+use tokio::sync::Notify;
 use tokio::time::sleep;
 use tracing::{debug, error, info, warn};
 
@@ -36,101 +57,170 @@ const AUTH_REQUIRED_MESSAGE: &str =
     "Авторизация в Gmail недоступна. Откройте окно настроек и выполните вход.";
 const AUTH_CONFIG_MESSAGE: &str =
     "Укажите OAuth Client ID и выполните авторизацию в настройках, чтобы продолжить.";
+const TRAY_ID: &str = "main";
+
+/// Подключённый почтовый ящик со своим циклом опроса.
+#[derive(Clone)]
+struct MailAccount {
+    email: String,
+    mail: Arc<dyn MailProvider>,
+    wakeup: Arc<Notify>,
+    degraded: Arc<AtomicBool>,
+}
 
 #[derive(Clone)]
 struct AppState {
     settings: Arc<SettingsManager>,
     oauth: Arc<OAuthController>,
-    gmail: Arc<GmailClient>,
+    seen: Arc<SeenStore>,
+    accounts: Arc<RwLock<Vec<MailAccount>>>,
+    backend: MailBackend,
     notifier: Arc<NotificationQueue>,
     auth_prompted: Arc<AtomicBool>,
-    snooze_until: Arc<Mutex<Option<std::time::Instant>>>,
+    /// Общая пауза и отложенные письма; сохраняются между запусками.
+    snooze: Arc<SnoozeStore>,
+    /// Будит цикл сроков, когда появился новый срок.
+    timers: Arc<Notify>,
+    /// Письма, которые правило `count_only` учло без показа; сбрасывается при переходе в почту.
+    counted: Arc<AtomicUsize>,
 }
 
 impl AppState {
     async fn poll_once(&self, app: &AppHandle) -> Result<()> {
         info!("poll_once: старт проверки");
+        if !self.ready_to_poll(app) {
+            return Ok(());
+        }
+        for account in self.accounts() {
+            self.poll_account(app, &account).await?;
+        }
+        info!("poll_once: проверка завершена");
+        Ok(())
+    }
 
-        if !self.oauth.is_configured() {
+    /// Общие для всех ящиков проверки перед опросом: OAuth и дайджест. Сроки snooze
+    /// отслеживает `spawn_timer_loop`. Во время snooze опрос продолжается, приостановлен
+    /// только показ (кроме VIP).
+    fn ready_to_poll(&self, app: &AppHandle) -> bool {
+        if self.backend == MailBackend::Gmail && !self.oauth.is_configured() {
             info!("poll_once: нет OAuth конфигурации, просим авторизацию");
             self.prompt_auth_once(app, AUTH_CONFIG_MESSAGE);
             info!("poll_once: выходим из проверки без запроса");
-            return Ok(());
+            return false;
         }
+        self.release_digest_if_due(app);
+        true
+    }
 
-        // Проверяем snooze один раз и сразу освобождаем блокировку
-        let snooze_expired = {
-            let lock = self.snooze_until.lock();
-            if let Some(until) = *lock {
-                if std::time::Instant::now() < until {
-                    debug!("gmail polling snoozed");
-                    return Ok(());
+    /// Снимает истёкшую общую паузу и возвращает в очередь письма, чей срок подошёл
+    /// и которые всё ещё не прочитаны.
+    async fn wake_snoozed(&self, app: &AppHandle) {
+        let due = self.snooze.take_due(chrono::Utc::now());
+        if due.resumed {
+            info!("snooze: время паузы истекло, возобновляем показ");
+            self.resume_display(app);
+        }
+        let settings = self.settings.get();
+        for notification in due.messages {
+            // Ящик могли отключить, пока письмо было отложено
+            let Some(account) = self.accounts().into_iter().find(|a| a.email == notification.account) else {
+                continue;
+            };
+            // Пока письмо ждало, его могли прочитать в другом клиенте или удалить.
+            // Если проверить не удалось, лучше показать лишний раз, чем потерять
+            let mut unread = false;
+            for id in notification.message_ids() {
+                unread = account.mail.is_unread(&id).await.unwrap_or_else(|err| {
+                    warn!(%err, %id, "snooze: не удалось проверить, прочитано ли письмо");
+                    true
+                });
+                if unread {
+                    break;
                 }
-                true // snooze истёк
-            } else {
-                false // snooze не был активен
             }
-        }; // блокировка освобождена
-
-        if snooze_expired {
-            info!("poll_once: время snooze истекло");
-            info!("poll_once: шаг 1 - сбрасываем snooze_until");
-            *self.snooze_until.lock() = None;
-            info!("poll_once: шаг 2 - snooze_until сброшен");
-
-            info!("poll_once: шаг 3 - получаем настройки");
-            let settings = self.settings.get();
-            info!("poll_once: шаг 4 - настройки получены");
+            if !unread {
+                info!(id = %notification.id, "snooze: письмо уже прочитано, не возвращаем");
+                continue;
+            }
+            info!(id = %notification.id, "snooze: возвращаем отложенное письмо");
+            if let Err(err) = self.notifier.enqueue(app, notification, &settings) {
+                warn!(%err, "не удалось показать отложенное письмо");
+            }
+        }
+    }
 
-            info!("poll_once: шаг 5 - вызываем notifier.replay_current");
-            let replay_result = self.notifier.replay_current(app, &settings);
-            info!("poll_once: шаг 6 - replay_current вернул результат");
+    /// В рабочее время показывает дайджест писем, скрытых расписанием.
+    fn release_digest_if_due(&self, app: &AppHandle) {
+        let settings = self.settings.get();
+        if settings.schedule.is_quiet(chrono::Utc::now()) {
+            return;
+        }
+        if let Err(err) = self.notifier.release_digest(app, &settings) {
+            warn!(%err, "не удалось показать дайджест за нерабочее время");
+        }
+    }
 
-            match replay_result {
-                Ok(true) => {
-                    info!("poll_once: шаг 7а - уведомление успешно показано повторно, завершаем проверку");
-                    return Ok(());
-                }
-                Ok(false) => {
-                    info!("poll_once: шаг 7б - текущего уведомления нет, очищаем кэш Gmail");
-                    self.gmail.clear_cache();
-                    info!("poll_once: шаг 8 - кэш Gmail очищен");
-                }
-                Err(err) => {
-                    warn!(%err, "poll_once: шаг 7в - ошибка повторного показа");
-                    self.gmail.clear_cache();
-                    info!("poll_once: шаг 8 - кэш Gmail очищен после ошибки");
-                }
-            }
-            info!("poll_once: шаг 9 - продолжаем к проверке Gmail");
+    /// Снимает паузу показа и показывает уведомление, дождавшееся своей очереди.
+    fn resume_display(&self, app: &AppHandle) {
+        self.notifier.set_held(false);
+        if let Err(err) = self.notifier.replay_current(app, &self.settings.get()) {
+            warn!(%err, "poll_once: ошибка повторного показа");
         }
+    }
 
-        info!("poll_once: отправляем запрос в Gmail на непрочитанные письма");
-        match self
-            .gmail
-            .fetch_unread(&self.settings.get().gmail_query)
+    async fn poll_account(&self, app: &AppHandle, account: &MailAccount) -> Result<()> {
+        info!(account = %account.email, "poll_once: отправляем запрос на непрочитанные письма");
+        let settings = self.settings.get();
+        let query = settings.query_for(&account.email);
+        match account
+            .mail
+            .list_new(&query, settings.max_messages_per_poll as usize)
             .await
         {
             Ok(messages) => {
                 info!("poll_once: Gmail вернул {count} писем", count = messages.len());
                 self.reset_auth_prompt();
-                let settings = self.settings.get();
-                for message in messages {
+                if account.degraded.swap(false, Ordering::SeqCst) {
+                    emit_degraded(app, &account.email, None);
+                }
+                let rules = RuleSet::new(&settings.rules);
+                let label_names = if rules.needs_labels() && !messages.is_empty() {
+                    label_names(account.mail.as_ref()).await
+                } else {
+                    HashMap::new()
+                };
+                for mut message in messages {
+                    message.apply_received_at(settings.received_at_source);
                     if let Ok(json) = serde_json::to_string(&message) {
                         debug!(notification_json = %json, "gmail: письмо для уведомления");
                     }
+                    message.vip = message.from.as_ref().is_some_and(|from| settings.is_vip(&from.email));
+                    if let Some(rule) = rules.evaluate(&message, &label_names) {
+                        info!(message_id = %message.id, rule = %rule.name, action = ?rule.action, "poll_once: сработало правило");
+                        let Some(routed) = self.apply_rule(app, account, message, &rule.action).await else {
+                            continue;
+                        };
+                        message = routed;
+                    }
                     if let Err(err) = self.notifier.enqueue(app, message, &settings) {
                         warn!(%err, "poll_once: не удалось добавить уведомление в очередь");
                     }
                 }
             }
             Err(err) => {
+                if let Some(api_err @ GmailApiError::Transient { .. }) = err.downcast_ref::<GmailApiError>() {
+                    // Сбой уже залогирован клиентом, здесь только сообщаем UI
+                    debug!(%err, "poll_once: Gmail временно недоступен");
+                    account.degraded.store(true, Ordering::SeqCst);
+                    emit_degraded(app, &account.email, Some(api_err));
+                    return Ok(());
+                }
                 warn!(%err, "poll_once: ошибка запроса в Gmail");
                 if let Some(kind) = err.downcast_ref::<OAuthError>() {
                     match kind {
                         OAuthError::NotAuthorised => {
                             warn!("poll_once: Gmail говорит что нет авторизации");
-                            self.notifier.clear();
+                            self.forget_unhandled();
                             self.prompt_auth_once(app, AUTH_REQUIRED_MESSAGE);
                         }
                         OAuthError::Misconfigured(reason) => {
@@ -142,12 +232,201 @@ impl AppState {
                 }
             }
         }
-        info!("poll_once: проверка завершена");
         Ok(())
     }
 
-    async fn mark_read(&self, id: &str) -> Result<()> {
-        self.gmail.mark_read(id).await
+    /// Выполняет действие правила. Возвращает письмо, если его всё же нужно показать.
+    async fn apply_rule(
+        &self,
+        app: &AppHandle,
+        account: &MailAccount,
+        mut message: GmailNotification,
+        action: &RuleAction,
+    ) -> Option<GmailNotification> {
+        let outcome = match action {
+            RuleAction::PlaySound { path } => {
+                message.sound_path = Some(path.clone());
+                return Some(message);
+            }
+            RuleAction::RaisePriority => {
+                message.priority = true;
+                return Some(message);
+            }
+            RuleAction::Suppress => NotificationOutcome::Filtered,
+            RuleAction::CountOnly => {
+                let count = self.counted.fetch_add(1, Ordering::SeqCst) + 1;
+                update_counted(app, count);
+                NotificationOutcome::Filtered
+            }
+            RuleAction::MarkRead => match account.mail.mark_read(&message.id).await {
+                Ok(()) => NotificationOutcome::Read,
+                Err(err) => {
+                    warn!(%err, message_id = %message.id, "rules: не удалось отметить прочитанным, показываем");
+                    return Some(message);
+                }
+            },
+            RuleAction::Archive => match account.mail.apply(&message.id, &MessageAction::Archive).await {
+                Ok(()) => NotificationOutcome::Archived,
+                Err(err) => {
+                    warn!(%err, message_id = %message.id, "rules: не удалось архивировать, показываем");
+                    return Some(message);
+                }
+            },
+        };
+        account.mail.record_outcome(&message.id, outcome);
+        None
+    }
+
+    fn accounts(&self) -> Vec<MailAccount> {
+        self.accounts.read().clone()
+    }
+
+    /// Ящик, из которого пришло уведомление. Без уведомления в очереди ящик неизвестен:
+    /// действие через чужой ящик хуже ошибки.
+    fn provider_for(&self, message_id: &str) -> Result<Arc<dyn MailProvider>> {
+        let email = self
+            .notifier
+            .find(message_id)
+            .map(|n| n.account)
+            .ok_or_else(|| anyhow::anyhow!("message {message_id} is not in the notification queue"))?;
+        self.accounts
+            .read()
+            .iter()
+            .find(|a| a.email == email)
+            .map(|account| account.mail.clone())
+            .ok_or_else(|| anyhow::anyhow!("mail account {email} is not connected"))
+    }
+
+    /// Итог записывается для всех писем уведомления, включая слитые в цепочку.
+    fn record_outcome(&self, message_id: &str, outcome: NotificationOutcome) {
+        let Ok(mail) = self.provider_for(message_id) else { return };
+        let ids = self
+            .notifier
+            .find(message_id)
+            .map(|n| n.message_ids())
+            .unwrap_or_else(|| vec![message_id.to_string()]);
+        for id in ids {
+            mail.record_outcome(&id, outcome);
+        }
+    }
+
+    fn reset_sync(&self) {
+        for account in self.accounts() {
+            account.mail.reset_sync();
+        }
+    }
+
+    /// Сбрасывает очередь; необработанные письма покажутся снова после повторного входа.
+    fn forget_unhandled(&self) {
+        let accounts = self.accounts();
+        for notification in self.notifier.clear() {
+            if let Some(account) = accounts.iter().find(|a| a.email == notification.account) {
+                for id in notification.message_ids() {
+                    account.mail.forget(&id);
+                }
+            }
+        }
+    }
+
+    /// Подключает ящик и запускает для него циклы опроса и push.
+    fn activate(&self, app: &AppHandle, email: &str, mail: Arc<dyn MailProvider>) -> MailAccount {
+        let account = MailAccount {
+            email: email.to_string(),
+            mail,
+            wakeup: Arc::new(Notify::new()),
+            degraded: Arc::new(AtomicBool::new(false)),
+        };
+        {
+            let mut accounts = self.accounts.write();
+            if let Some(existing) = accounts.iter().find(|a| a.email == email) {
+                return existing.clone();
+            }
+            accounts.push(account.clone());
+        }
+        info!(account = email, "подключаем почтовый ящик");
+        spawn_account_loops(app.clone(), self.clone(), account.clone());
+        account
+    }
+
+    fn activate_gmail(&self, app: &AppHandle, email: &str) -> Result<MailAccount> {
+        let api_url = self.settings.get().endpoints.gmail_api_url();
+        let client = GmailClient::new(
+            email,
+            api_url,
+            self.oauth.token_provider(email),
+            self.seen.clone(),
+        )?;
+        Ok(self.activate(app, email, Arc::new(client)))
+    }
+
+    fn deactivate(&self, email: &str) {
+        let removed: Vec<MailAccount> = {
+            let mut accounts = self.accounts.write();
+            let (removed, kept) = accounts.drain(..).partition(|a| a.email == email);
+            *accounts = kept;
+            removed
+        };
+        // Будим циклы, чтобы они заметили отключение и завершились
+        for account in removed {
+            account.wakeup.notify_one();
+        }
+    }
+
+    fn is_active(&self, account: &MailAccount) -> bool {
+        self.accounts
+            .read()
+            .iter()
+            .any(|a| Arc::ptr_eq(&a.wakeup, &account.wakeup))
+    }
+
+    /// Есть ли хотя бы один Gmail-аккаунт с рабочими токенами.
+    async fn any_authorised(&self) -> bool {
+        for account in self.settings.get().accounts {
+            if wait_for_authorisation(self.oauth.token_provider(&account.email)).await {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Вход в ещё один Gmail-аккаунт: сохраняем его в настройках и сразу опрашиваем.
+    async fn authorise_account(&self, app: &AppHandle) -> Result<String> {
+        let result = self.oauth.authorise(app).await?;
+        let settings = self.settings.add_account(&result.email)?;
+        if let Err(err) = app.emit("gmail://settings", &settings) {
+            warn!(%err, "failed to broadcast settings");
+        }
+        if self.backend == MailBackend::Gmail {
+            let account = self.activate_gmail(app, &result.email)?;
+            self.reset_auth_prompt();
+            self.poll_account(app, &account).await?;
+        }
+        Ok(result.email)
+    }
+
+    async fn revoke_accounts(&self, app: &AppHandle, account: Option<String>) -> Result<()> {
+        let emails = match account {
+            Some(email) => vec![email],
+            None => self
+                .settings
+                .get()
+                .accounts
+                .into_iter()
+                .map(|a| a.email)
+                .collect(),
+        };
+        self.forget_unhandled();
+        for email in emails {
+            self.oauth.revoke_account(&email).await?;
+            if self.backend == MailBackend::Gmail {
+                self.deactivate(&email);
+            }
+            let settings = self.settings.remove_account(&email)?;
+            if let Err(err) = app.emit("gmail://settings", &settings) {
+                warn!(%err, "failed to broadcast settings");
+            }
+        }
+        Ok(())
     }
 
     fn reset_auth_prompt(&self) {
@@ -173,8 +452,7 @@ impl AppState {
 #[tauri::command]
 async fn initialise(state: tauri::State<'_, AppState>) -> Result<InitialPayload, String> {
     let settings = state.settings.get();
-    let provider: Arc<dyn AccessTokenProvider> = state.oauth.clone();
-    let authorised = wait_for_authorisation(provider).await;
+    let authorised = state.any_authorised().await;
     Ok(InitialPayload {
         settings,
         authorised,
@@ -192,13 +470,15 @@ async fn request_authorisation(
     app: AppHandle,
     state: tauri::State<'_, AppState>,
 ) -> Result<(), String> {
-    let res = state.oauth.authorise(&app).await;
+    let res = state.authorise_account(&app).await;
     if let Err(ref err) = res {
         error!(?err, "authorisation failed");
     }
-    res.map_err(stringify_error)?;
-    state.oauth.load_cached();
-    state.poll_once(&app).await.map_err(|err| err.to_string())?;
+    let email = res.map_err(|err| match err.downcast::<OAuthError>() {
+        Ok(err) => stringify_error(err),
+        Err(err) => format!("{err:#}"),
+    })?;
+    info!(%email, "authorisation completed");
     // Hide settings window after successful authorisation, leave in tray
     if let Some(win) = app.get_webview_window("main") {
         let _ = win.hide();
@@ -218,10 +498,15 @@ fn stringify_error<E: std::error::Error>(err: E) -> String {
 }
 
 #[tauri::command]
-async fn revoke(state: tauri::State<'_, AppState>) -> Result<(), String> {
-    state.oauth.revoke().await.map_err(|err| err.to_string())?;
-    state.notifier.clear();
-    Ok(())
+async fn revoke(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    account: Option<String>,
+) -> Result<(), String> {
+    state
+        .revoke_accounts(&app, account)
+        .await
+        .map_err(|err| err.to_string())
 }
 
 #[tauri::command]
@@ -245,28 +530,39 @@ async fn update_settings(
 async fn check_now(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
     info!("check_now: manual check requested");
 
-    if !state.oauth.is_configured() {
-        state.prompt_auth_force(&app, AUTH_CONFIG_MESSAGE);
-        return Ok(());
-    }
+    if state.backend == MailBackend::Gmail {
+        if !state.oauth.is_configured() {
+            state.prompt_auth_force(&app, AUTH_CONFIG_MESSAGE);
+            return Ok(());
+        }
 
-    if let Err(err) = state.oauth.access_token().await {
-        match err {
-            OAuthError::NotAuthorised => {
-                state.prompt_auth_force(&app, AUTH_REQUIRED_MESSAGE);
-                return Ok(());
-            }
-            OAuthError::Misconfigured(reason) => {
-                state.prompt_auth_force(&app, reason.as_str());
-                return Ok(());
+        let accounts = state.settings.get().accounts;
+        if accounts.is_empty() {
+            state.prompt_auth_force(&app, AUTH_REQUIRED_MESSAGE);
+            return Ok(());
+        }
+
+        // Неавторизованный аккаунт не мешает проверить остальные
+        for account in accounts {
+            if let Err(err) = state.oauth.token_provider(&account.email).access_token().await {
+                match err {
+                    OAuthError::NotAuthorised => {
+                        state.prompt_auth_force(&app, AUTH_REQUIRED_MESSAGE);
+                    }
+                    OAuthError::Misconfigured(reason) => {
+                        state.prompt_auth_force(&app, reason.as_str());
+                        return Ok(());
+                    }
+                    _ => return Err(err.to_string()),
+                }
             }
-            _ => return Err(err.to_string()),
         }
     }
 
     // Сбрасываем режим отложения при принудительной проверке
-    let was_snoozed = state.snooze_until.lock().is_some();
-    *state.snooze_until.lock() = None;
+    let was_snoozed = state.snooze.until().is_some();
+    state.snooze.set_until(None);
+    state.notifier.set_held(false);
     if was_snoozed {
         info!("check_now: snooze cleared");
 
@@ -277,17 +573,25 @@ async fn check_now(app: tauri::AppHandle, state: tauri::State<'_, AppState>) ->
             info!("check_now: уведомление из очереди показано, завершаем");
             return Ok(());
         }
-        info!("check_now: в очереди нет уведомлений, очищаем кэш Gmail");
+        info!("check_now: в очереди нет уведомлений, сбрасываем синхронизацию Gmail");
 
-        // Если уведомления нет, очищаем кэш и продолжаем
-        state.gmail.clear_cache();
-        info!("check_now: Gmail cache cleared");
+        // Если уведомления нет, сбрасываем синхронизацию и продолжаем
+        state.reset_sync();
+        info!("check_now: Gmail sync reset");
     }
 
     info!("check_now: calling poll_once");
     state.poll_once(&app).await.map_err(|err| err.to_string())
 }
 
+#[tauri::command]
+async fn set_imap_password(
+    state: tauri::State<'_, AppState>,
+    password: String,
+) -> Result<(), String> {
+    imap::store_password(&state.settings.get().imap, &password).map_err(|err| err.to_string())
+}
+
 #[tauri::command]
 async fn mark_message_read(
     app: AppHandle,
@@ -296,17 +600,260 @@ async fn mark_message_read(
 ) -> Result<(), String> {
     let notifier = state.notifier.clone();
     let settings = state.settings.get();
-    state
-        .mark_read(&message_id)
-        .await
-        .map_err(|err| err.to_string())?;
-    state.gmail.forget(&message_id);
+    if !is_summary_id(&message_id) {
+        let mail = state
+            .provider_for(&message_id)
+            .map_err(|err| err.to_string())?;
+        // Слитую цепочку отмечаем прочитанной целиком
+        let thread = state
+            .notifier
+            .find(&message_id)
+            .filter(|n| !n.merged_ids.is_empty());
+        match thread {
+            Some(thread) => mail.mark_thread_read(&thread.thread_id, &thread.message_ids()).await,
+            None => mail.mark_read(&message_id).await,
+        }
+        .map_err(|err| command_error(&app, &state, err))?;
+        state.record_outcome(&message_id, NotificationOutcome::Read);
+    }
     notifier
         .complete_current(&app, &settings)
         .map_err(|err| err.to_string())?;
     Ok(())
 }
 
+/// Действие над письмом (для слитой цепочки — над всей цепочкой) и переход к следующему уведомлению.
+/// При ошибке уведомление остаётся текущим: окно его не прячет и показывает ошибку.
+async fn act_on_message(
+    app: &AppHandle,
+    state: &AppState,
+    message_id: &str,
+    action: MessageAction,
+    outcome: NotificationOutcome,
+) -> Result<(), String> {
+    if is_summary_id(message_id) {
+        return Err("summary notification has no message to act on".into());
+    }
+    let mail = state.provider_for(message_id).map_err(|err| err.to_string())?;
+    let thread = state
+        .notifier
+        .find(message_id)
+        .filter(|n| !n.merged_ids.is_empty());
+    info!(%message_id, ?action, "message action");
+    match thread {
+        Some(thread) => {
+            mail.apply_to_thread(&thread.thread_id, &thread.message_ids(), &action)
+                .await
+        }
+        None => mail.apply(message_id, &action).await,
+    }
+    .map_err(|err| command_error(app, state, err))?;
+    state.record_outcome(message_id, outcome);
+    state
+        .notifier
+        .complete_current(app, &state.settings.get())
+        .map_err(|err| err.to_string())
+}
+
+/// Ошибка команды для UI; при отозванном доступе заодно просим войти заново.
+fn command_error(app: &AppHandle, state: &AppState, err: anyhow::Error) -> String {
+    if matches!(err.downcast_ref::<OAuthError>(), Some(OAuthError::NotAuthorised)) {
+        state.prompt_auth_once(app, AUTH_REQUIRED_MESSAGE);
+    }
+    err.to_string()
+}
+
+#[tauri::command]
+async fn archive_message(app: AppHandle, state: tauri::State<'_, AppState>, message_id: String) -> Result<(), String> {
+    act_on_message(&app, &state, &message_id, MessageAction::Archive, NotificationOutcome::Archived).await
+}
+
+#[tauri::command]
+async fn trash_message(app: AppHandle, state: tauri::State<'_, AppState>, message_id: String) -> Result<(), String> {
+    act_on_message(&app, &state, &message_id, MessageAction::Trash, NotificationOutcome::Trashed).await
+}
+
+#[tauri::command]
+async fn star_message(app: AppHandle, state: tauri::State<'_, AppState>, message_id: String) -> Result<(), String> {
+    act_on_message(&app, &state, &message_id, MessageAction::Star, NotificationOutcome::Labelled).await
+}
+
+#[tauri::command]
+async fn mark_important(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    message_id: String,
+    important: bool,
+) -> Result<(), String> {
+    act_on_message(
+        &app,
+        &state,
+        &message_id,
+        MessageAction::Important(important),
+        NotificationOutcome::Labelled,
+    )
+    .await
+}
+
+#[tauri::command]
+async fn apply_label(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    message_id: String,
+    label_id: String,
+) -> Result<(), String> {
+    act_on_message(
+        &app,
+        &state,
+        &message_id,
+        MessageAction::Label(label_id),
+        NotificationOutcome::Labelled,
+    )
+    .await
+}
+
+/// Метки ящика, из которого пришло уведомление.
+#[tauri::command]
+async fn list_labels(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    message_id: String,
+) -> Result<Vec<MailLabel>, String> {
+    let mail = state.provider_for(&message_id).map_err(|err| err.to_string())?;
+    mail.labels().await.map_err(|err| command_error(&app, &state, err))
+}
+
+/// Быстрый ответ из уведомления; после отправки показываем следующее.
+#[tauri::command]
+async fn send_reply(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    message_id: String,
+    body: String,
+) -> Result<(), String> {
+    reply_to_message(&app, &state, &message_id, &body).await
+}
+
+#[tauri::command]
+async fn list_reply_templates(state: tauri::State<'_, AppState>) -> Result<Vec<ReplyTemplate>, String> {
+    Ok(state.settings.get().reply_templates)
+}
+
+/// Ответ шаблоном: переменные подставляются из полей уведомления.
+#[tauri::command]
+async fn send_template_reply(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    message_id: String,
+    template: String,
+) -> Result<(), String> {
+    let settings = state.settings.get();
+    let template = settings
+        .reply_templates
+        .iter()
+        .find(|t| t.name == template)
+        .ok_or_else(|| format!("Шаблон «{template}» не найден"))?;
+    let notification = state
+        .notifier
+        .find(&message_id)
+        .ok_or_else(|| format!("Уведомление {message_id} уже закрыто"))?;
+    let body = template.render(&notification);
+    reply_to_message(&app, &state, &message_id, &body).await
+}
+
+async fn reply_to_message(app: &AppHandle, state: &AppState, message_id: &str, body: &str) -> Result<(), String> {
+    if body.trim().is_empty() {
+        return Err("Пустой ответ".into());
+    }
+    if !state.settings.get().quick_reply_enabled {
+        return Err("Быстрые ответы выключены в настройках".into());
+    }
+    let mail = state.provider_for(message_id).map_err(|err| err.to_string())?;
+    info!(%message_id, "sending quick reply");
+    mail.send_reply(message_id, body)
+        .await
+        .map_err(|err| command_error(app, state, err))?;
+    state.record_outcome(message_id, NotificationOutcome::Replied);
+    state
+        .notifier
+        .complete_current(app, &state.settings.get())
+        .map_err(|err| err.to_string())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RuleVerdict {
+    message_id: String,
+    account: String,
+    subject: String,
+    sender: Option<String>,
+    rule: Option<String>,
+    action: Option<RuleAction>,
+}
+
+/// Пробный прогон правил по текущим непрочитанным: ничего не меняет и не показывает.
+/// Без `rules` проверяются сохранённые в настройках.
+#[tauri::command]
+async fn dry_run_rules(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    rules: Option<Vec<Rule>>,
+) -> Result<Vec<RuleVerdict>, String> {
+    let settings = state.settings.get();
+    let rules = rules.unwrap_or_else(|| settings.rules.clone());
+    rules::validate(&rules).map_err(|err| format!("{err:#}"))?;
+    let set = RuleSet::new(&rules);
+    let mut verdicts = Vec::new();
+    for account in state.accounts() {
+        let query = settings.query_for(&account.email);
+        let messages = account
+            .mail
+            .peek(&query, settings.max_messages_per_poll as usize)
+            .await
+            .map_err(|err| command_error(&app, &state, err))?;
+        let label_names = if set.needs_labels() {
+            label_names(account.mail.as_ref()).await
+        } else {
+            HashMap::new()
+        };
+        for message in messages {
+            let rule = set.evaluate(&message, &label_names);
+            verdicts.push(RuleVerdict {
+                rule: rule.map(|r| r.name.clone()),
+                action: rule.map(|r| r.action.clone()),
+                message_id: message.id,
+                account: message.account,
+                subject: message.subject,
+                sender: message.sender,
+            });
+        }
+    }
+    Ok(verdicts)
+}
+
+/// Имена меток по идентификатору; без них правила сравнивают только идентификаторы.
+async fn label_names(mail: &dyn MailProvider) -> HashMap<String, String> {
+    match mail.labels().await {
+        Ok(labels) => labels.into_iter().map(|label| (label.id, label.name)).collect(),
+        Err(err) => {
+            warn!(%err, "rules: не удалось загрузить метки");
+            HashMap::new()
+        }
+    }
+}
+
+/// Счётчик писем без показа — в подсказке иконки в трее и для UI.
+fn update_counted(app: &AppHandle, count: usize) {
+    let tooltip = match count {
+        0 => "Gmail Tray Notifier".to_string(),
+        count => format!("Gmail Tray Notifier — без уведомления: {count}"),
+    };
+    if let Some(tray) = app.tray_by_id(TRAY_ID) {
+        let _ = tray.set_tooltip(Some(tooltip));
+    }
+    let _ = app.emit("gmail://counted", count);
+}
+
 #[tauri::command]
 async fn open_in_browser(
     app: AppHandle,
@@ -314,10 +861,20 @@ async fn open_in_browser(
     url: String,
 ) -> Result<(), String> {
     let settings = state.settings.get();
+    if let Some(current) = state.notifier.current() {
+        state.record_outcome(&current.id, NotificationOutcome::Opened);
+    }
     state
         .notifier
         .complete_current(&app, &settings)
         .map_err(|err| err.to_string())?;
+    if state.counted.swap(0, Ordering::SeqCst) > 0 {
+        update_counted(&app, 0);
+    }
+    // У IMAP-ящика без веб-интерфейса ссылки нет
+    if url.is_empty() {
+        return Ok(());
+    }
     webbrowser::open(&url)
         .map_err(|err| err.to_string())
         .map(|_| ())
@@ -376,7 +933,7 @@ async fn dismiss_notification(
     message_id: Option<String>,
 ) -> Result<(), String> {
     if let Some(id) = message_id {
-        state.gmail.forget(&id);
+        state.record_outcome(&id, NotificationOutcome::Dismissed);
     }
     let settings = state.settings.get();
     state
@@ -385,24 +942,103 @@ async fn dismiss_notification(
         .map_err(|err| err.to_string())
 }
 
+/// Без `target` — на `snooze_duration_mins` из настроек. Возвращает срок окончания паузы.
 #[tauri::command]
-async fn snooze(app: AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
-    let duration_mins = state.settings.get().snooze_duration_mins;
-    let duration = Duration::from_secs(duration_mins * 60);
+async fn snooze(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    target: Option<SnoozeTarget>,
+) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    let target = target.unwrap_or(SnoozeTarget::Minutes {
+        minutes: state.settings.get().snooze_duration_mins,
+    });
+    let until = target.deadline(chrono::Utc::now()).map_err(|err| err.to_string())?;
 
-    info!("snooze: setting snooze for {} minutes", duration_mins);
-    *state.snooze_until.lock() = Some(std::time::Instant::now() + duration);
+    info!(%until, "snooze: откладываем уведомления");
+    state.snooze.set_until(Some(until));
+    state.notifier.set_held(true);
+    state.timers.notify_one();
 
-    // Скрываем окно уведомления, но не очищаем очередь
-    // Уведомления появятся снова после окончания snooze
+    // Скрываем окно уведомления, но не очищаем очередь: опрос продолжается,
+    // VIP показываются сразу, остальные — после окончания snooze
     if let Some(win) = app.get_webview_window("alert") {
         let _ = win.hide();
     }
 
     info!("snooze: window hidden, snooze active");
+    Ok(until)
+}
+
+#[tauri::command]
+async fn cancel_snooze(app: AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    if state.snooze.until().is_some() {
+        info!("snooze: пауза снята вручную");
+        state.snooze.set_until(None);
+        state.resume_display(&app);
+    }
     Ok(())
 }
 
+/// Убирает текущее уведомление до срока; остальные письма показываются как обычно.
+#[tauri::command]
+async fn snooze_message(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    message_id: String,
+    target: SnoozeTarget,
+) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    let until = target.deadline(chrono::Utc::now()).map_err(|err| err.to_string())?;
+    let notification = state
+        .notifier
+        .current()
+        .filter(|n| n.id == message_id)
+        .ok_or_else(|| "уведомление уже закрыто".to_string())?;
+    info!(%message_id, %until, "snooze: откладываем письмо");
+    state.snooze.snooze_message(notification, until);
+    state.timers.notify_one();
+    state
+        .notifier
+        .complete_current(&app, &state.settings.get())
+        .map_err(|err| err.to_string())?;
+    Ok(until)
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SnoozedPayload {
+    message_id: String,
+    account: String,
+    subject: String,
+    until: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SnoozeStatus {
+    until: Option<chrono::DateTime<chrono::Utc>>,
+    messages: Vec<SnoozedPayload>,
+}
+
+#[tauri::command]
+fn snooze_status(state: tauri::State<'_, AppState>) -> SnoozeStatus {
+    let mut messages: Vec<SnoozedPayload> = state
+        .snooze
+        .messages()
+        .into_iter()
+        .map(|m| SnoozedPayload {
+            message_id: m.notification.id,
+            account: m.notification.account,
+            subject: m.notification.subject,
+            until: m.until,
+        })
+        .collect();
+    messages.sort_by_key(|m| m.until);
+    SnoozeStatus {
+        until: state.snooze.until(),
+        messages,
+    }
+}
+
 fn resolve_voice_dir(app: &AppHandle) -> Result<PathBuf> {
     const DEV_VOICE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../frontend/public/voice");
     if let Ok(path) = app.path().resolve("voice", BaseDirectory::Resource) {
@@ -444,7 +1080,143 @@ fn format_voice_label(stem: &str) -> String {
 async fn current_notification(
     state: tauri::State<'_, AppState>,
 ) -> Result<Option<GmailNotification>, String> {
-    Ok(state.notifier.current())
+    Ok(state.notifier.current().map(|n| n.localised(chrono::Utc::now())))
+}
+
+#[tauri::command]
+async fn fetch_message(
+    state: tauri::State<'_, AppState>,
+    message_id: String,
+) -> Result<GmailNotification, String> {
+    let mail = state
+        .provider_for(&message_id)
+        .map_err(|err| err.to_string())?;
+    let mut message = mail.fetch(&message_id).await.map_err(|err| err.to_string())?;
+    message.apply_received_at(state.settings.get().received_at_source);
+    Ok(message.localised(chrono::Utc::now()))
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageBodyPayload {
+    #[serde(flatten)]
+    body: SanitisedBody,
+    sender: Option<String>,
+    images_allowed: bool,
+}
+
+#[tauri::command]
+async fn get_message_body(
+    state: tauri::State<'_, AppState>,
+    message_id: String,
+) -> Result<Option<MessageBodyPayload>, String> {
+    if is_summary_id(&message_id) {
+        return Ok(None);
+    }
+    let mail = state
+        .provider_for(&message_id)
+        .map_err(|err| err.to_string())?;
+    let Some(raw) = mail.body(&message_id).await.map_err(|err| err.to_string())? else {
+        return Ok(None);
+    };
+    // Письмо не попадает в окно уведомления без очистки
+    let sender = state
+        .notifier
+        .find(&message_id)
+        .and_then(|n| n.from.map(|from| from.email).or(n.sender));
+    let images_allowed = sender
+        .as_deref()
+        .is_some_and(|sender| state.settings.get().images_allowed_for(sender));
+    let body = match raw {
+        MailBody::Html(html) => sanitise_html(&html, images_allowed),
+        MailBody::Text(text) => sanitise_text(&text),
+    };
+    Ok(Some(MessageBodyPayload {
+        body,
+        sender,
+        images_allowed,
+    }))
+}
+
+/// Сохраняет вложение в «Загрузки» и возвращает путь к файлу.
+#[tauri::command]
+async fn download_attachment(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    message_id: String,
+    attachment_id: String,
+    filename: String,
+) -> Result<String, String> {
+    let mail = state
+        .provider_for(&message_id)
+        .map_err(|err| err.to_string())?;
+    let bytes = mail
+        .attachment(&message_id, &attachment_id)
+        .await
+        .map_err(|err| err.to_string())?;
+    let dir = app.path().download_dir().map_err(|err| err.to_string())?;
+    let path = save_unique(&dir, &filename, &bytes).map_err(|err| err.to_string())?;
+    info!(%message_id, path = %path.display(), "attachment saved");
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Имя файла из письма может содержать пути и запрещённые символы,
+/// существующие файлы не перезаписываем: `name (1).ext`, `name (2).ext`…
+fn save_unique(dir: &Path, filename: &str, bytes: &[u8]) -> Result<PathBuf> {
+    let cleaned: String = filename
+        .chars()
+        .map(|c| if c.is_control() || r#"/\:*?"<>|"#.contains(c) { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').trim();
+    let name = Path::new(if cleaned.is_empty() { "attachment" } else { cleaned });
+    let stem = name.file_stem().and_then(|s| s.to_str()).unwrap_or("attachment");
+    let ext = name.extension().and_then(|e| e.to_str());
+
+    fs::create_dir_all(dir)?;
+    for n in 0..1000 {
+        let candidate = match (n, ext) {
+            (0, _) => name.to_path_buf(),
+            (_, Some(ext)) => PathBuf::from(format!("{stem} ({n}).{ext}")),
+            (_, None) => PathBuf::from(format!("{stem} ({n})")),
+        };
+        let path = dir.join(candidate);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                std::io::Write::write_all(&mut file, bytes)?;
+                return Ok(path);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    anyhow::bail!("too many files named {}", name.display())
+}
+
+#[tauri::command]
+async fn set_sender_images(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    sender: String,
+    allowed: bool,
+) -> Result<Settings, String> {
+    let settings = state
+        .settings
+        .set_sender_images(&sender, allowed)
+        .map_err(|err| err.to_string())?;
+    if let Err(err) = app.emit("gmail://settings", &settings) {
+        warn!(%err, "failed to broadcast settings");
+    }
+    Ok(settings)
+}
+
+fn snooze_from_tray(app: &AppHandle, target: Option<SnoozeTarget>) {
+    info!(?target, "tray click: snooze");
+    let app_clone = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(err) = snooze(app_clone.clone(), app_clone.state(), target).await {
+            warn!(%err, "snooze from tray failed");
+        }
+    });
 }
 
 fn register_tray(app: &tauri::App) -> tauri::Result<()> {
@@ -456,13 +1228,25 @@ fn register_tray(app: &tauri::App) -> tauri::Result<()> {
         true,
         None::<&str>,
     )?;
-    let auth = MenuItem::with_id(app, "auth", "Войти в Gmail", true, None::<&str>)?;
-    let logout = MenuItem::with_id(app, "logout", "Выйти", true, None::<&str>)?;
+    let auth = MenuItem::with_id(app, "auth", "Добавить аккаунт Gmail", true, None::<&str>)?;
+    let logout = MenuItem::with_id(app, "logout", "Выйти из всех аккаунтов", true, None::<&str>)?;
     let quit = MenuItem::with_id(app, "quit", "Выйти из приложения", true, None::<&str>)?;
+    let snooze_default = MenuItem::with_id(app, "snooze_default", "На время из настроек", true, None::<&str>)?;
+    let snooze_hour = MenuItem::with_id(app, "snooze_hour", "На час", true, None::<&str>)?;
+    let snooze_tomorrow = MenuItem::with_id(app, "snooze_tomorrow", "До завтра 9:00", true, None::<&str>)?;
+    let snooze_cancel = MenuItem::with_id(app, "snooze_cancel", "Снять паузу", true, None::<&str>)?;
+    let snooze_menu = SubmenuBuilder::new(app, "Отложить уведомления")
+        .item(&snooze_default)
+        .item(&snooze_hour)
+        .item(&snooze_tomorrow)
+        .separator()
+        .item(&snooze_cancel)
+        .build()?;
 
     let menu = MenuBuilder::new(app)
         .item(&check_now_item)
         .item(&open_settings)
+        .item(&snooze_menu)
         .separator()
         .item(&auth)
         .item(&logout)
@@ -476,7 +1260,7 @@ fn register_tray(app: &tauri::App) -> tauri::Result<()> {
             .expect("missing default icon")
     });
 
-    TrayIconBuilder::new()
+    TrayIconBuilder::with_id(TRAY_ID)
         .icon(tray_icon)
         .menu(&menu)
         .tooltip("Gmail Tray Notifier")
@@ -493,6 +1277,21 @@ fn register_tray(app: &tauri::App) -> tauri::Result<()> {
                     }
                 });
             }
+            "snooze_default" => snooze_from_tray(app_handle, None),
+            "snooze_hour" => snooze_from_tray(app_handle, Some(SnoozeTarget::Minutes { minutes: 60 })),
+            "snooze_tomorrow" => snooze_from_tray(
+                app_handle,
+                Some(SnoozeTarget::Tomorrow {
+                    time: "09:00".into(),
+                }),
+            ),
+            "snooze_cancel" => {
+                info!("tray click: snooze_cancel");
+                let app_clone = app_handle.clone();
+                tauri::async_runtime::spawn(async move {
+                    let _ = cancel_snooze(app_clone.clone(), app_clone.state()).await;
+                });
+            }
             "open_settings" => {
                 info!("tray click: open_settings");
                 if let Some(win) = app_handle.get_webview_window("main") {
@@ -505,16 +1304,17 @@ fn register_tray(app: &tauri::App) -> tauri::Result<()> {
                 let auth_handle = app_handle.clone();
                 let app_state = auth_handle.state::<AppState>().inner().clone();
                 tauri::async_runtime::spawn(async move {
-                    if let Err(err) = app_state.oauth.authorise(&auth_handle).await {
+                    if let Err(err) = app_state.authorise_account(&auth_handle).await {
                         warn!(%err, "authorisation from tray failed");
                     }
                 });
             }
             "logout" => {
                 info!("tray click: logout");
+                let logout_handle = app_handle.clone();
                 let app_state = app_handle.state::<AppState>().inner().clone();
                 tauri::async_runtime::spawn(async move {
-                    if let Err(err) = app_state.oauth.revoke().await {
+                    if let Err(err) = app_state.revoke_accounts(&logout_handle, None).await {
                         warn!(%err, "failed to revoke tokens");
                     }
                 });
@@ -581,47 +1381,77 @@ fn main() {
             let settings = Arc::new(SettingsManager::initialize(&app_handle)?);
             let oauth = Arc::new(OAuthController::new(settings.clone()));
             oauth.load_cached();
-            let token_provider: Arc<dyn AccessTokenProvider> = oauth.clone();
-            let gmail = Arc::new(GmailClient::new(token_provider)?);
-            let notifier = Arc::new(NotificationQueue::new());
+            let seen = Arc::new(SeenStore::initialize()?);
+            // Бэкенд выбирается при запуске; смена в настройках вступает в силу после перезапуска
+            let backend = settings.get().mail_backend;
+            // Дайджест за нерабочее время переживает перезапуск
+            let notifier = Arc::new(NotificationQueue::initialize()?);
+            // Пауза, начатая до перезапуска, продолжается до своего срока
+            let snooze = Arc::new(SnoozeStore::initialize()?);
+            notifier.set_held(snooze.until().is_some());
 
             app.manage(AppState {
                 settings: settings.clone(),
                 oauth: oauth.clone(),
-                gmail: gmail.clone(),
+                seen: seen.clone(),
+                accounts: Arc::new(RwLock::new(Vec::new())),
+                backend,
                 notifier: notifier.clone(),
                 auth_prompted: Arc::new(AtomicBool::new(false)),
-                snooze_until: Arc::new(Mutex::new(None)),
+                snooze: snooze.clone(),
+                timers: Arc::new(Notify::new()),
+                counted: Arc::new(AtomicUsize::new(0)),
             });
 
             ensure_autostart(&app_handle, settings.get().auto_launch);
 
             register_tray(app)?;
 
-            // На старте скрываем окно, если уже авторизованы
-            let hide_handle = app_handle.clone();
-            let provider: Arc<dyn AccessTokenProvider> = oauth.clone();
-            tauri::async_runtime::spawn(async move {
-                if wait_for_authorisation(provider).await {
-                    if let Some(win) = hide_handle.get_webview_window("main") {
-                        let _ = win.hide();
+            let app_state = app.state::<AppState>().inner().clone();
+            spawn_timer_loop(app_handle.clone(), app_state.clone());
+            match backend {
+                MailBackend::Gmail => {
+                    for account in settings.get().accounts {
+                        app_state.activate_gmail(&app_handle, &account.email)?;
                     }
                 }
-            });
+                MailBackend::Imap => {
+                    let username = settings.get().imap.username;
+                    let mail = Arc::new(ImapClient::new(settings.clone(), seen));
+                    app_state.activate(&app_handle, &username, mail);
+                }
+            }
 
-            let app_state = app.state::<AppState>().inner().clone();
-            let poll_handle = app_handle.clone();
+            // Токены старых версий лежат в одной записи keychain без привязки к аккаунту
+            let startup_handle = app_handle.clone();
             tauri::async_runtime::spawn(async move {
-                info!("polling loop started");
-                loop {
-                    let interval = app_state.settings.get().poll_interval_secs;
-                    debug!("polling loop: calling poll_once");
-                    if let Err(err) = app_state.poll_once(&poll_handle).await {
-                        warn!(%err, "polling failed");
+                match app_state.oauth.migrate_legacy_tokens().await {
+                    Ok(Some(email)) => {
+                        info!(%email, "oauth: перенесён аккаунт из старой версии");
+                        if let Err(err) = app_state.settings.add_account(&email) {
+                            warn!(%err, "failed to save migrated account");
+                        } else if backend == MailBackend::Gmail {
+                            if let Err(err) = app_state.activate_gmail(&startup_handle, &email) {
+                                warn!(%err, "failed to activate migrated account");
+                            }
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(err) => warn!(%err, "oauth: не удалось перенести старые токены"),
+                }
+
+                // На старте скрываем окно, если уже авторизованы
+                if app_state.any_authorised().await {
+                    if let Some(win) = startup_handle.get_webview_window("main") {
+                        let _ = win.hide();
+                    }
+                } else if backend == MailBackend::Gmail {
+                    // Без аккаунтов циклов опроса нет, просим войти отсюда
+                    if !app_state.oauth.is_configured() {
+                        app_state.prompt_auth_once(&startup_handle, AUTH_CONFIG_MESSAGE);
+                    } else if app_state.accounts().is_empty() {
+                        app_state.prompt_auth_once(&startup_handle, AUTH_REQUIRED_MESSAGE);
                     }
-                    debug!("polling loop: sleeping for {} seconds", interval.max(15));
-                    sleep(Duration::from_secs(interval.max(15))).await;
-                    debug!("polling loop: woke up, next iteration");
                 }
             });
 
@@ -634,16 +1464,124 @@ fn main() {
             update_settings,
             check_now,
             mark_message_read,
+            archive_message,
+            trash_message,
+            star_message,
+            mark_important,
+            apply_label,
+            list_labels,
+            send_reply,
+            list_reply_templates,
+            send_template_reply,
+            dry_run_rules,
             open_in_browser,
             dismiss_notification,
             snooze,
+            cancel_snooze,
+            snooze_message,
+            snooze_status,
             current_notification,
+            fetch_message,
+            get_message_body,
+            download_attachment,
+            set_sender_images,
+            set_imap_password,
             list_voice_tracks
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
 
+/// Цикл опроса ящика и ожидание push-сигналов от него. Оба завершаются после отключения ящика.
+fn spawn_account_loops(app: AppHandle, state: AppState, account: MailAccount) {
+    let poll_state = state.clone();
+    let poll_account = account.clone();
+    tauri::async_runtime::spawn(async move {
+        info!(account = %poll_account.email, "polling loop started");
+        while poll_state.is_active(&poll_account) {
+            let interval = poll_state.settings.get().poll_interval_secs;
+            debug!("polling loop: calling poll_account");
+            if poll_state.ready_to_poll(&app) {
+                if let Err(err) = poll_state.poll_account(&app, &poll_account).await {
+                    warn!(%err, "polling failed");
+                }
+            }
+            debug!("polling loop: sleeping for {} seconds", interval.max(15));
+            tokio::select! {
+                _ = sleep(Duration::from_secs(interval.max(15))) => {}
+                _ = poll_account.wakeup.notified() => {
+                    debug!("polling loop: разбужен push-уведомлением");
+                }
+            }
+            debug!("polling loop: woke up, next iteration");
+        }
+        info!(account = %poll_account.email, "polling loop stopped");
+    });
+
+    // Push-уведомления бэкенда (IMAP IDLE) будят цикл опроса досрочно
+    tauri::async_runtime::spawn(async move {
+        while state.is_active(&account) {
+            match account.mail.wait_for_change().await {
+                Ok(()) => account.wakeup.notify_one(),
+                Err(err) => {
+                    warn!(%err, "push: ошибка ожидания изменений в ящике");
+                    sleep(Duration::from_secs(30)).await;
+                }
+            }
+        }
+    });
+}
+
+/// Конец snooze, отложенные письма и дайджест появляются в срок, не дожидаясь очередного опроса.
+/// Просыпаемся не реже раза в пять минут, чтобы подхватить изменения расписания.
+fn spawn_timer_loop(app: AppHandle, state: AppState) {
+    const MAX_WAIT: Duration = Duration::from_secs(5 * 60);
+    tauri::async_runtime::spawn(async move {
+        loop {
+            state.wake_snoozed(&app).await;
+            state.release_digest_if_due(&app);
+            let now = chrono::Utc::now();
+            let wait = [state.settings.get().schedule.next_start(now), state.snooze.next_deadline()]
+                .into_iter()
+                .flatten()
+                .min()
+                .map_or(MAX_WAIT, |at| {
+                    (at - now).to_std().unwrap_or_default().clamp(Duration::from_secs(1), MAX_WAIT)
+                });
+            tokio::select! {
+                _ = sleep(wait) => {}
+                _ = state.timers.notified() => {}
+            }
+        }
+    });
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DegradedPayload {
+    account: String,
+    degraded: bool,
+    reason: Option<String>,
+    retry_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Сообщает UI, что Gmail отвечает ошибками (`error` задан) или снова доступен.
+fn emit_degraded(app: &AppHandle, account: &str, error: Option<&GmailApiError>) {
+    let (reason, retry_at) = match error {
+        Some(GmailApiError::Transient { reason, retry_at }) => (Some(reason.clone()), Some(*retry_at)),
+        _ => (None, None),
+    };
+    let payload = DegradedPayload {
+        account: account.to_string(),
+        degraded: error.is_some(),
+        reason,
+        retry_at,
+    };
+    if let Err(err) = app.emit("gmail://degraded", &payload) {
+        warn!(%err, "failed to notify degraded state");
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 struct AuthPromptPayload {
     message: String,
@@ -1,42 +1,159 @@
 use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::Utc;
 use parking_lot::Mutex;
 use serde::Serialize;
 use tauri::{AppHandle, Emitter, Manager, PhysicalPosition, PhysicalSize, WebviewWindow};
 use tracing::{info, warn};
 
-use crate::{config::Settings, gmail::GmailNotification};
+use crate::{
+    config::{project_dirs, Settings},
+    gmail::GmailNotification,
+};
 
 #[derive(Default)]
 pub struct NotificationQueue {
     inner: Mutex<QueueState>,
+    /// Файл дайджеста: его письма уже отмечены показанными и без него потерялись бы при перезапуске.
+    digest_path: Option<PathBuf>,
 }
 
 #[derive(Default)]
 struct QueueState {
     current: Option<GmailNotification>,
     pending: VecDeque<GmailNotification>,
+    /// Показ приостановлен (snooze): очередь копится, на экран попадают только VIP.
+    /// Текущее уведомление при этом может быть назначено, но не показано.
+    held: bool,
+    /// Письма, скрытые расписанием в нерабочее время; показываются одним дайджестом.
+    digest: Vec<GmailNotification>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Placement {
+    /// Показать (или обновить) текущее уведомление.
+    Show,
+    /// Письмо ждёт в очереди, отдельно или внутри своей цепочки, либо показ приостановлен.
+    Queued,
+}
+
+impl QueueState {
+    fn place(&mut self, notification: GmailNotification) -> Placement {
+        let urgent = self.held && notification.vip;
+        if let Some(current) = self.current.as_mut().filter(|c| c.same_thread(&notification)) {
+            current.absorb(notification);
+            return self.placement_of_current();
+        }
+        if let Some(index) = self.pending.iter().position(|p| p.same_thread(&notification)) {
+            let pending = &mut self.pending[index];
+            let was_priority = pending.priority;
+            pending.absorb(notification);
+            if urgent || (pending.priority && !was_priority) {
+                let thread = self.pending.remove(index).expect("index is in range");
+                return self.push(thread, urgent);
+            }
+            return Placement::Queued;
+        }
+        self.push(notification, urgent)
+    }
+
+    /// `urgent` — VIP во время паузы: вытесняет текущее, которое возвращается в начало очереди.
+    fn push(&mut self, notification: GmailNotification, urgent: bool) -> Placement {
+        if urgent {
+            if let Some(previous) = self.current.replace(notification) {
+                self.pending.push_front(previous);
+            }
+            return Placement::Show;
+        }
+        if self.current.is_none() {
+            self.current = Some(notification);
+            return self.placement_of_current();
+        }
+        self.push_pending(notification);
+        Placement::Queued
+    }
+
+    fn placement_of_current(&self) -> Placement {
+        match &self.current {
+            Some(current) if !self.held || current.vip => Placement::Show,
+            _ => Placement::Queued,
+        }
+    }
+
+    /// Следующее уведомление после закрытия текущего и нужно ли его показывать.
+    /// Во время паузы сначала берём VIP, остальные назначаются текущими без показа.
+    fn advance(&mut self) -> Option<(GmailNotification, bool)> {
+        let vip = self.held.then(|| self.pending.iter().position(|p| p.vip)).flatten();
+        let next = match vip {
+            Some(index) => self.pending.remove(index),
+            None => self.pending.pop_front(),
+        }?;
+        self.current = Some(next.clone());
+        let show = !self.held || next.vip;
+        Some((next, show))
+    }
+
+    /// Приоритетные встают за уже ожидающими приоритетными, перед обычными.
+    fn push_pending(&mut self, notification: GmailNotification) {
+        let index = if notification.priority {
+            self.pending.iter().position(|p| !p.priority).unwrap_or(self.pending.len())
+        } else {
+            self.pending.len()
+        };
+        self.pending.insert(index, notification);
+    }
 }
 
 impl NotificationQueue {
-    pub fn new() -> Self {
+    /// Очередь с дайджестом, сохранённым на диске.
+    pub fn initialize() -> Result<Self> {
+        let proj = project_dirs()?;
+        let dir = proj.data_dir();
+        if !dir.exists() {
+            fs::create_dir_all(dir).context("Failed to create data directory")?;
+        }
+        Ok(Self::open(dir.join("digest.json")))
+    }
+
+    pub fn open(path: PathBuf) -> Self {
+        let digest = load_digest(&path).unwrap_or_else(|err| {
+            warn!(%err, path = %path.display(), "digest is unreadable, starting empty");
+            Vec::new()
+        });
         Self {
-            inner: Mutex::new(QueueState::default()),
+            inner: Mutex::new(QueueState {
+                digest,
+                ..QueueState::default()
+            }),
+            digest_path: Some(path),
         }
     }
 
     pub fn enqueue(&self, app: &AppHandle, notification: GmailNotification, settings: &Settings) -> Result<()> {
         info!("notifier.enqueue: получено уведомление {}", notification.id);
         let mut state = self.inner.lock();
-        if state.current.is_none() {
-            info!("notifier.enqueue: текущего уведомления нет, показываем сразу");
-            state.current = Some(notification.clone());
-            drop(state);
-            emit_notification(app, notification, settings)?;
-        } else {
-            info!("notifier.enqueue: есть активное ��ведомление, кладём в очередь ({} элементов)", state.pending.len());
-            state.pending.push_back(notification);
+        if !notification.vip && settings.schedule.suppresses(Utc::now()) {
+            info!("notifier.enqueue: нерабочее время, {} уходит в дайджест", notification.id);
+            state.digest.push(notification);
+            self.persist_digest(&state.digest);
+            return Ok(());
+        }
+        match state.place(notification) {
+            Placement::Show => {
+                // Новое уведомление или новое письмо в уже показанной цепочке
+                let current = state.current.clone();
+                drop(state);
+                if let Some(current) = current {
+                    info!("notifier.enqueue: показываем {} (писем в цепочке: {})", current.id, current.merged_ids.len() + 1);
+                    emit_notification(app, current, settings)?;
+                }
+            }
+            Placement::Queued => {
+                info!("notifier.enqueue: есть активное ��ведомление, кладём в очередь ({} элементов)", state.pending.len());
+            }
         }
         Ok(())
     }
@@ -45,6 +162,17 @@ impl NotificationQueue {
         self.inner.lock().current.clone()
     }
 
+    /// Ищет уведомление среди показанного и ожидающих, в том числе по слитым письмам цепочки.
+    pub fn find(&self, id: &str) -> Option<GmailNotification> {
+        let state = self.inner.lock();
+        state
+            .current
+            .iter()
+            .chain(state.pending.iter())
+            .find(|notification| notification.id == id || notification.merged_ids.iter().any(|m| m == id))
+            .cloned()
+    }
+
     pub fn replay_current(&self, app: &AppHandle, settings: &Settings) -> Result<bool> {
         info!("notifier.replay_current: шаг 1 - начало функции");
         info!("notifier.replay_current: шаг 2 - блокируем mutex для чтения current");
@@ -70,24 +198,82 @@ impl NotificationQueue {
         info!("notifier.complete_current: завершаем текущее уведомление");
         let mut state = self.inner.lock();
         state.current = None;
-        if let Some(next) = state.pending.pop_front() {
-            info!("notifier.complete_current: берём следующее уведомление {}", next.id);
-            state.current = Some(next.clone());
-            drop(state);
-            emit_notification(app, next, settings)?;
-        } else if let Some(win) = app.get_webview_window("alert") {
-            info!("notifier.complete_current: очередь пуста, скрываем окно");
-            let _ = win.hide();
+        match state.advance() {
+            Some((next, true)) => {
+                info!("notifier.complete_current: берём следующее уведомление {}", next.id);
+                drop(state);
+                emit_notification(app, next, settings)?;
+            }
+            next => {
+                if let Some((next, _)) = next {
+                    info!("notifier.complete_current: показ приостановлен, {} ждёт", next.id);
+                }
+                if let Some(win) = app.get_webview_window("alert") {
+                    info!("notifier.complete_current: скрываем окно");
+                    let _ = win.hide();
+                }
+            }
         }
         Ok(())
     }
 
-    pub fn clear(&self) {
+    /// Приостанавливает или возобновляет показ. Возвращает прежнее состояние.
+    pub fn set_held(&self, held: bool) -> bool {
+        std::mem::replace(&mut self.inner.lock().held, held)
+    }
+
+    /// Показывает письма, накопленные за нерабочее время, одним уведомлением.
+    pub fn release_digest(&self, app: &AppHandle, settings: &Settings) -> Result<bool> {
+        let messages = {
+            let mut state = self.inner.lock();
+            if state.digest.is_empty() {
+                return Ok(false);
+            }
+            let messages = std::mem::take(&mut state.digest);
+            self.persist_digest(&state.digest);
+            messages
+        };
+        info!("notifier.release_digest: дайджест из {} писем", messages.len());
+        self.enqueue(app, GmailNotification::digest(&messages), settings)?;
+        Ok(true)
+    }
+
+    /// Очищает очередь и возвращает уведомления, которые так и не были обработаны.
+    pub fn clear(&self) -> Vec<GmailNotification> {
         info!("notifier.clear: очищаем все уведомления");
         let mut state = self.inner.lock();
-        state.current = None;
-        state.pending.clear();
+        let mut dropped: Vec<GmailNotification> = state.current.take().into_iter().collect();
+        dropped.extend(state.pending.drain(..));
+        if !state.digest.is_empty() {
+            dropped.append(&mut state.digest);
+            self.persist_digest(&state.digest);
+        }
+        dropped
+    }
+
+    fn persist_digest(&self, digest: &[GmailNotification]) {
+        let Some(path) = &self.digest_path else { return };
+        if let Err(err) = save_digest(path, digest) {
+            warn!(%err, path = %path.display(), "failed to persist digest");
+        }
+    }
+}
+
+fn load_digest(path: &Path) -> Result<Vec<GmailNotification>> {
+    if !path.exists() {
+        return Ok(Vec::new());
     }
+    let bytes = fs::read(path).context("Failed to read digest")?;
+    serde_json::from_slice(&bytes).context("Invalid digest file")
+}
+
+fn save_digest(path: &Path, digest: &[GmailNotification]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create parent directory")?;
+    }
+    let json = serde_json::to_vec(digest).context("Failed to serialise digest")?;
+    fs::write(path, json).context("Failed to write digest")?;
+    Ok(())
 }
 
 fn emit_notification(app: &AppHandle, notification: GmailNotification, settings: &Settings) -> Result<()> {
@@ -120,6 +306,9 @@ fn emit_notification(app: &AppHandle, notification: GmailNotification, settings:
     }
 
     info!("emit_notification: шаг 12 - отправляем событие gmail://notification");
+    let now = Utc::now();
+    let mut notification = notification.localised(now);
+    notification.silent = !notification.vip && settings.schedule.silences(now);
     app.emit("gmail://notification", &notification)?;
     info!("emit_notification: шаг 13 - событие отправлено, функция завершена");
     Ok(())
@@ -163,3 +352,108 @@ pub fn notify_dismissed(app: &AppHandle, handled: bool) {
         warn!(%err, "failed to notify dismissal");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, thread: &str, minute: i64) -> GmailNotification {
+        let mut n = GmailNotification::for_test(id, thread);
+        n.sender = Some(format!("{id}@example.com"));
+        n.received_at = chrono::DateTime::from_timestamp(minute * 60, 0);
+        n
+    }
+
+    #[test]
+    fn digest_survives_restart() {
+        let path = std::env::temp_dir().join(format!("digest-{}.json", uuid::Uuid::new_v4()));
+        save_digest(&path, &[message("a1", "a", 1), message("b1", "b", 2)]).unwrap();
+        let queue = NotificationQueue::open(path.clone());
+        let dropped: Vec<String> = queue.clear().into_iter().map(|n| n.id).collect();
+        assert_eq!(dropped, ["a1", "b1"]);
+        assert!(NotificationQueue::open(path.clone()).clear().is_empty(), "cleared digest is not restored");
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn merges_messages_of_one_thread() {
+        let mut state = QueueState::default();
+        assert_eq!(state.place(message("a1", "a", 1)), Placement::Show);
+        assert_eq!(state.place(message("b1", "b", 2)), Placement::Queued);
+        // Ответ в показанной цепочке обновляет текущее уведомление
+        assert_eq!(state.place(message("a2", "a", 3)), Placement::Show);
+        for (id, minute) in [("b2", 4), ("b3", 5)] {
+            assert_eq!(state.place(message(id, "b", minute)), Placement::Queued);
+        }
+        // Запоздавшее старое письмо не подменяет более новое
+        assert_eq!(state.place(message("b0", "b", 0)), Placement::Queued);
+
+        let current = state.current.as_ref().unwrap();
+        assert_eq!(current.id, "a2");
+        assert_eq!(current.merged_ids, ["a1"]);
+        assert_eq!(state.pending.len(), 1);
+        let thread = &state.pending[0];
+        assert_eq!(thread.sender.as_deref(), Some("b3@example.com"));
+        assert_eq!(thread.message_ids(), ["b1", "b2", "b0", "b3"]);
+
+        // Сводка «и ещё N писем» ни с чем не сливается
+        let summary = GmailNotification::overflow_summary("me@example.com", 5, "");
+        assert_eq!(state.place(summary), Placement::Queued);
+        assert_eq!(state.pending.len(), 2);
+    }
+
+    #[test]
+    fn only_vip_messages_show_while_held() {
+        let mut state = QueueState {
+            held: true,
+            ..QueueState::default()
+        };
+        let vip = |id: &str, thread: &str| {
+            let mut n = message(id, thread, 1);
+            n.vip = true;
+            n
+        };
+        assert_eq!(state.place(message("a1", "a", 1)), Placement::Queued, "assigned but hidden");
+        assert_eq!(state.place(message("b1", "b", 1)), Placement::Queued);
+        assert_eq!(state.place(vip("v1", "v")), Placement::Show);
+        assert_eq!(state.current.as_ref().unwrap().id, "v1");
+        // Вытесненное уведомление вернулось в начало очереди
+        assert_eq!(state.pending[0].id, "a1");
+        // VIP-письмо в ожидающей цепочке поднимает её на экран
+        assert_eq!(state.place(vip("b2", "b")), Placement::Show);
+        assert_eq!(state.current.as_ref().unwrap().message_ids(), ["b1", "b2"]);
+
+        state.current = None;
+        state.place(vip("w1", "w"));
+        state.current = None;
+        let (next, show) = state.advance().unwrap();
+        assert_eq!((next.id.as_str(), show), ("v1", true), "VIP goes first while held");
+        state.current = None;
+        let (next, show) = state.advance().unwrap();
+        assert_eq!((next.id.as_str(), show), ("a1", false));
+
+        state.held = false;
+        assert_eq!(state.placement_of_current(), Placement::Show, "replayed after the pause");
+    }
+
+    #[test]
+    fn priority_messages_jump_the_queue() {
+        let mut state = QueueState::default();
+        let urgent = |id: &str, thread: &str| {
+            let mut n = message(id, thread, 1);
+            n.priority = true;
+            n
+        };
+        state.place(message("a1", "a", 1));
+        state.place(message("b1", "b", 1));
+        state.place(message("c1", "c", 1));
+        state.place(urgent("d1", "d"));
+        state.place(urgent("e1", "e"));
+        // Срочное письмо в уже ожидающей цепочке поднимает всю цепочку
+        state.place(urgent("c2", "c"));
+
+        let order: Vec<&str> = state.pending.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(order, ["d1", "e1", "c2", "b1"]);
+        assert_eq!(state.current.as_ref().unwrap().id, "a1", "current is not preempted");
+    }
+}
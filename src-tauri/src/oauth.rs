@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -19,10 +20,12 @@ use tokio::time::timeout;
 use tracing::{debug, warn};
 
 use crate::config::SettingsManager;
+use crate::gmail::HTTP_TIMEOUT;
 
 const OAUTH_REDIRECT_PORT: u16 = 42813;
-const SERVICE_NAME: &str = "gmail_tray_notifier";
-const TOKEN_USER: &str = "gmail";
+pub(crate) const SERVICE_NAME: &str = "gmail_tray_notifier";
+// Единственная запись keychain версий до поддержки нескольких аккаунтов
+const LEGACY_TOKEN_USER: &str = "gmail";
 
 #[derive(thiserror::Error, Debug)]
 pub enum OAuthError {
@@ -37,15 +40,13 @@ pub enum OAuthError {
 #[async_trait]
 pub trait AccessTokenProvider: Send + Sync {
     async fn access_token(&self) -> Result<String, OAuthError>;
-    async fn revoke(&self) -> Result<(), OAuthError>;
-    fn is_configured(&self) -> bool;
 }
 
 #[derive(Clone)]
 pub struct OAuthController {
     settings: Arc<SettingsManager>,
     client: OnceCell<BasicClient>,
-    cache: Arc<Mutex<Option<TokenSet>>>,
+    cache: Arc<Mutex<HashMap<String, TokenSet>>>,
     storage: TokenStorage,
 }
 
@@ -54,7 +55,7 @@ impl OAuthController {
         Self {
             settings,
             client: OnceCell::new(),
-            cache: Arc::new(Mutex::new(None)),
+            cache: Arc::new(Mutex::new(HashMap::new())),
             storage: TokenStorage::new(),
         }
     }
@@ -70,9 +71,9 @@ impl OAuthController {
                 }
                 let client_id = ClientId::new(settings.oauth_client_id.clone());
                 let secret = settings.oauth_client_secret.clone().map(ClientSecret::new);
-                let auth_url = AuthUrl::new("https://accounts.google.com/o/oauth2/v2/auth".into())
+                let auth_url = AuthUrl::new(settings.endpoints.oauth_auth_url())
                     .map_err(|err| OAuthError::Other(err.into()))?;
-                let token_url = TokenUrl::new("https://oauth2.googleapis.com/token".into())
+                let token_url = TokenUrl::new(settings.endpoints.oauth_token_url())
                     .map_err(|err| OAuthError::Other(err.into()))?;
                 let redirect_url = RedirectUrl::new(format!(
                     "http://localhost:{}/oauth2callback",
@@ -87,10 +88,18 @@ impl OAuthController {
             .await
     }
 
+    /// Токены конкретного аккаунта для `GmailClient`.
+    pub fn token_provider(self: &Arc<Self>, email: &str) -> Arc<dyn AccessTokenProvider> {
+        Arc::new(AccountTokens {
+            controller: self.clone(),
+            email: email.to_string(),
+        })
+    }
+
     pub async fn authorise(&self, _app: &AppHandle) -> Result<AuthorisationResult, OAuthError> {
         let client = self.ensure_client().await?;
         let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
-        let (auth_url, csrf_state) = client
+        let mut request = client
             .authorize_url(CsrfToken::new_random)
             .set_pkce_challenge(pkce_challenge)
             .add_scope(Scope::new(
@@ -99,7 +108,15 @@ impl OAuthController {
             .add_scope(Scope::new(
                 "https://www.googleapis.com/auth/userinfo.email".into(),
             ))
-            .url();
+            // Даём выбрать аккаунт, а не молча переиспользовать последний
+            .add_extra_param("prompt", "select_account consent");
+        // Отправку писем запрашиваем, только если пользователь включил быстрые ответы
+        if self.settings.get().quick_reply_enabled {
+            request = request.add_scope(Scope::new(
+                "https://www.googleapis.com/auth/gmail.send".into(),
+            ));
+        }
+        let (auth_url, csrf_state) = request.url();
 
         debug!(url = %auth_url, "oauth: open browser for authorisation");
         webbrowser::open(&auth_url.to_string()).map_err(|err| OAuthError::Other(err.into()))?;
@@ -120,25 +137,63 @@ impl OAuthController {
             expires_at = ?token_set.expires_at,
             "oauth: token received"
         );
-        self.storage.store(&token_set)?;
-        {
-            let mut cache = self.cache.lock();
-            *cache = Some(token_set.clone());
-        }
-        Ok(AuthorisationResult {})
+        let email = self.fetch_account_email(&token_set.access_token).await?;
+        debug!(%email, "oauth: account resolved");
+        self.storage.store(&email, &token_set)?;
+        self.cache.lock().insert(email.clone(), token_set);
+        Ok(AuthorisationResult { email })
     }
 
     pub fn load_cached(&self) {
-        if let Ok(stored) = self.storage.load() {
-            let mut cache = self.cache.lock();
-            *cache = Some(stored);
+        let settings = self.settings.get();
+        let mut cache = self.cache.lock();
+        for account in &settings.accounts {
+            if let Ok(stored) = self.storage.load(&account.email) {
+                cache.insert(account.email.clone(), stored);
+            }
         }
     }
 
-    async fn refresh_if_needed(&self, client: &BasicClient) -> Result<Option<String>, OAuthError> {
+    /// Переносит токены из единственной записи keychain старых версий в запись аккаунта.
+    pub async fn migrate_legacy_tokens(&self) -> Result<Option<String>, OAuthError> {
+        let Ok(legacy) = self.storage.load_legacy() else {
+            return Ok(None);
+        };
+        let client = self.ensure_client().await?;
+        let mut tokens = legacy;
+        if tokens.is_expired() {
+            let Some(refresh) = tokens.refresh_token.clone() else {
+                return Ok(None);
+            };
+            tokens = self.exchange_refresh(client, refresh).await?;
+        }
+        let email = self.fetch_account_email(&tokens.access_token).await?;
+        self.storage.store(&email, &tokens)?;
+        self.storage.clear_legacy()?;
+        self.cache.lock().insert(email.clone(), tokens);
+        debug!(%email, "oauth: legacy tokens migrated");
+        Ok(Some(email))
+    }
+
+    async fn exchange_refresh(&self, client: &BasicClient, refresh: String) -> Result<TokenSet, OAuthError> {
+        let response = client
+            .exchange_refresh_token(&RefreshToken::new(refresh.clone()))
+            .request_async(async_http_client)
+            .await
+            .map_err(|err| OAuthError::Other(err.into()))?;
+        let mut new_token = TokenSet::from_response(&response)?;
+
+        // If the new token doesn't have a refresh token, preserve the old one
+        if new_token.refresh_token.is_none() {
+            new_token.refresh_token = Some(refresh);
+        }
+        Ok(new_token)
+    }
+
+    async fn refresh_if_needed(&self, client: &BasicClient, email: &str) -> Result<Option<String>, OAuthError> {
         let refresh_token = {
             let mut cache = self.cache.lock();
-            if let Some(token) = cache.as_mut() {
+            if let Some(token) = cache.get_mut(email) {
                 if !token.is_expired() {
                     return Ok(Some(token.access_token.clone()));
                 }
@@ -149,89 +204,116 @@ impl OAuthController {
         };
 
         if let Some(refresh) = refresh_token {
-            let response = client
-                .exchange_refresh_token(&RefreshToken::new(refresh.clone()))
-                .request_async(async_http_client)
-                .await
-                .map_err(|err| OAuthError::Other(err.into()))?;
-            let mut new_token = TokenSet::from_response(&response)?;
-            
-            // If the new token doesn't have a refresh token, preserve the old one
-            if new_token.refresh_token.is_none() {
-                new_token.refresh_token = Some(refresh);
-            }
-
-            self.storage.store(&new_token)?;
+            let new_token = self.exchange_refresh(client, refresh).await?;
+            self.storage.store(email, &new_token)?;
             let mut cache = self.cache.lock();
-            *cache = Some(new_token.clone());
+            cache.insert(email.to_string(), new_token.clone());
             return Ok(Some(new_token.access_token));
         }
 
         Ok(None)
     }
 
-    async fn token_from_storage(&self) -> Result<Option<String>, OAuthError> {
-        let client = match self.ensure_client().await {
-            Ok(client) => client,
-            Err(err) => return Err(err),
-        };
-        if let Some(token) = self.refresh_if_needed(client).await? {
+    async fn token_from_storage(&self, email: &str) -> Result<Option<String>, OAuthError> {
+        let client = self.ensure_client().await?;
+        if let Some(token) = self.refresh_if_needed(client, email).await? {
             return Ok(Some(token));
         }
         let cache = self.cache.lock();
-        if let Some(token) = cache.clone() {
+        if let Some(token) = cache.get(email).cloned() {
             return Ok(Some(token.access_token));
         }
         drop(cache);
-        if let Ok(stored) = self.storage.load() {
+        if let Ok(stored) = self.storage.load(email) {
             let token = stored.access_token.clone();
-            {
-                let mut cache = self.cache.lock();
-                *cache = Some(stored);
-            }
+            self.cache.lock().insert(email.to_string(), stored);
             return Ok(Some(token));
         }
         Err(OAuthError::NotAuthorised)
     }
-}
-
-// no custom HTTP client needed; oauth2 + reqwest with rustls is enabled via Cargo features
 
-#[async_trait]
-impl AccessTokenProvider for OAuthController {
-    async fn access_token(&self) -> Result<String, OAuthError> {
+    async fn access_token_for(&self, email: &str) -> Result<String, OAuthError> {
         let client = self.ensure_client().await?;
-        if let Some(token) = self.refresh_if_needed(client).await? {
+        if let Some(token) = self.refresh_if_needed(client, email).await? {
             return Ok(token);
         }
         {
             let cache = self.cache.lock();
-            if let Some(token) = cache.clone() {
+            if let Some(token) = cache.get(email) {
                 if !token.is_expired() {
-                    return Ok(token.access_token);
+                    return Ok(token.access_token.clone());
                 }
             }
         }
-        self.token_from_storage()
+        self.token_from_storage(email)
             .await?
             .ok_or(OAuthError::NotAuthorised)
     }
 
-    async fn revoke(&self) -> Result<(), OAuthError> {
-        self.storage.clear()?;
-        let mut cache = self.cache.lock();
-        *cache = None;
+    pub async fn revoke_account(&self, email: &str) -> Result<(), OAuthError> {
+        self.storage.clear(email)?;
+        self.cache.lock().remove(email);
         Ok(())
     }
 
-    fn is_configured(&self) -> bool {
+    async fn fetch_account_email(&self, access_token: &str) -> Result<String, OAuthError> {
+        let url = format!("{}/profile", self.settings.get().endpoints.gmail_api_url());
+        // Без таймаута зависший запрос профиля навсегда остановил бы вход
+        let http = reqwest::Client::builder()
+            .use_rustls_tls()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .map_err(|err| OAuthError::Other(err.into()))?;
+        let response = http
+            .get(url)
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .map_err(|err| OAuthError::Other(err.into()))?;
+        if !response.status().is_success() {
+            return Err(OAuthError::Other(anyhow!(
+                "gmail profile returned status {}",
+                response.status()
+            )));
+        }
+        let profile: GmailProfile = response
+            .json()
+            .await
+            .map_err(|err| OAuthError::Other(err.into()))?;
+        Ok(profile.email_address)
+    }
+
+    pub fn is_configured(&self) -> bool {
         let settings = self.settings.get();
         !settings.oauth_client_id.trim().is_empty()
     }
 }
 
+// no custom HTTP client needed; oauth2 + reqwest with rustls is enabled via Cargo features
+
+struct AccountTokens {
+    controller: Arc<OAuthController>,
+    email: String,
+}
+
+#[async_trait]
+impl AccessTokenProvider for AccountTokens {
+    async fn access_token(&self) -> Result<String, OAuthError> {
+        self.controller.access_token_for(&self.email).await
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct AuthorisationResult {}
+pub struct AuthorisationResult {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GmailProfile {
+    email_address: String,
+}
+
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct TokenSet {
@@ -271,6 +353,7 @@ impl TokenSet {
     }
 }
 
+/// Токены в keychain: одна запись на аккаунт, ключ — e-mail.
 #[derive(Clone, Default)]
 struct TokenStorage;
 
@@ -279,9 +362,12 @@ impl TokenStorage {
         Self
     }
 
-    fn store(&self, tokens: &TokenSet) -> Result<(), OAuthError> {
-        let entry = keyring::Entry::new(SERVICE_NAME, TOKEN_USER)
-            .map_err(|err| OAuthError::Other(err.into()))?;
+    fn entry(user: &str) -> Result<keyring::Entry, OAuthError> {
+        keyring::Entry::new(SERVICE_NAME, user).map_err(|err| OAuthError::Other(err.into()))
+    }
+
+    fn store(&self, email: &str, tokens: &TokenSet) -> Result<(), OAuthError> {
+        let entry = Self::entry(email)?;
         let serialized =
             serde_json::to_string(tokens).map_err(|err| OAuthError::Other(err.into()))?;
         entry
@@ -290,9 +376,15 @@ impl TokenStorage {
         Ok(())
     }
 
-    fn load(&self) -> Result<TokenSet, OAuthError> {
-        let entry = keyring::Entry::new(SERVICE_NAME, TOKEN_USER)
-            .map_err(|err| OAuthError::Other(err.into()))?;
+    fn load(&self, email: &str) -> Result<TokenSet, OAuthError> {
+        Self::read(&Self::entry(email)?)
+    }
+
+    fn load_legacy(&self) -> Result<TokenSet, OAuthError> {
+        Self::read(&Self::entry(LEGACY_TOKEN_USER)?)
+    }
+
+    fn read(entry: &keyring::Entry) -> Result<TokenSet, OAuthError> {
         let serialized = entry
             .get_password()
             .map_err(|_| OAuthError::NotAuthorised)?;
@@ -301,9 +393,15 @@ impl TokenStorage {
         Ok(tokens)
     }
 
-    fn clear(&self) -> Result<(), OAuthError> {
-        let entry = keyring::Entry::new(SERVICE_NAME, TOKEN_USER)
-            .map_err(|err| OAuthError::Other(err.into()))?;
+    fn clear(&self, email: &str) -> Result<(), OAuthError> {
+        Self::delete(&Self::entry(email)?)
+    }
+
+    fn clear_legacy(&self) -> Result<(), OAuthError> {
+        Self::delete(&Self::entry(LEGACY_TOKEN_USER)?)
+    }
+
+    fn delete(entry: &keyring::Entry) -> Result<(), OAuthError> {
         match entry.delete_password() {
             Ok(_) | Err(keyring::Error::NoEntry) => Ok(()),
             Err(err) => Err(OAuthError::Other(err.into())),
@@ -0,0 +1,86 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::gmail::GmailNotification;
+use crate::seen::NotificationOutcome;
+
+/// Тело письма для развёрнутого вида: HTML проходит очистку, текст только экранируется.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MailBody {
+    Html(String),
+    Text(String),
+}
+
+/// Действия над письмом из уведомления, кроме «Прочитано».
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageAction {
+    Archive,
+    Trash,
+    Star,
+    Important(bool),
+    /// Идентификатор метки из `MailProvider::labels`.
+    Label(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MailLabel {
+    pub id: String,
+    pub name: String,
+}
+
+/// Источник почты для уведомлений. `GmailClient` — одна из реализаций;
+/// другие бэкенды (и фейки в тестах) подключаются через этот же трейт.
+#[async_trait]
+pub trait MailProvider: Send + Sync {
+    /// Письма, появившиеся с прошлого опроса и ещё не показанные пользователю.
+    async fn list_new(&self, query: &str, limit: usize) -> Result<Vec<GmailNotification>>;
+    /// Непрочитанные письма без побочных эффектов — для пробного прогона правил.
+    async fn peek(&self, _query: &str, _limit: usize) -> Result<Vec<GmailNotification>> {
+        bail!("preview is not supported by this mail backend")
+    }
+    async fn fetch(&self, id: &str) -> Result<GmailNotification>;
+    /// Письмо всё ещё непрочитано и лежит в ящике. Бэкенды, которые не умеют
+    /// проверить, считают его непрочитанным.
+    async fn is_unread(&self, _id: &str) -> Result<bool> {
+        Ok(true)
+    }
+    /// Тело письма для развёрнутого вида уведомления.
+    async fn body(&self, id: &str) -> Result<Option<MailBody>>;
+    /// Содержимое вложения по идентификатору из `GmailNotification::attachments`.
+    async fn attachment(&self, message_id: &str, attachment_id: &str) -> Result<Vec<u8>>;
+    async fn mark_read(&self, id: &str) -> Result<()>;
+    /// Отмечает прочитанной всю цепочку. Без поддержки цепочек на сервере —
+    /// по одному письму из `ids`.
+    async fn mark_thread_read(&self, _thread_id: &str, ids: &[String]) -> Result<()> {
+        for id in ids {
+            self.mark_read(id).await?;
+        }
+        Ok(())
+    }
+    async fn apply(&self, id: &str, action: &MessageAction) -> Result<()> {
+        bail!("{action:?} is not supported for message {id}")
+    }
+    async fn apply_to_thread(&self, _thread_id: &str, ids: &[String], action: &MessageAction) -> Result<()> {
+        for id in ids {
+            self.apply(id, action).await?;
+        }
+        Ok(())
+    }
+    async fn labels(&self) -> Result<Vec<MailLabel>> {
+        Ok(Vec::new())
+    }
+    /// Ответ отправителю в ту же цепочку.
+    async fn send_reply(&self, id: &str, _body: &str) -> Result<()> {
+        bail!("replying is not supported for message {id}")
+    }
+    fn record_outcome(&self, id: &str, outcome: NotificationOutcome);
+    fn forget(&self, id: &str);
+    /// Следующий `list_new` заново просмотрит весь ящик, не повторяя уже показанные письма.
+    fn reset_sync(&self);
+
+    /// Ждёт push-сигнала об изменениях в ящике. Бэкенды без push никогда не завершаются.
+    async fn wait_for_change(&self) -> Result<()> {
+        std::future::pending().await
+    }
+}
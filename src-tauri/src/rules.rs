@@ -0,0 +1,227 @@
+//! Локальные правила для новых писем: проверяются при опросе до постановки в очередь.
+//! Срабатывает первое подходящее включённое правило; условия внутри правила — через «и».
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::gmail::GmailNotification;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rule {
+    pub name: String,
+    pub enabled: bool,
+    /// Адрес отправителя целиком, без учёта регистра.
+    pub sender: Option<String>,
+    /// Домен отправителя; поддомены тоже подходят.
+    pub domain: Option<String>,
+    /// Регулярное выражение по теме, без учёта регистра.
+    pub subject: Option<String>,
+    /// Метка: идентификатор (`CATEGORY_PROMOTIONS`, `Label_1`) или имя пользовательской метки.
+    pub label: Option<String>,
+    /// Адрес среди получателей (To или Cc).
+    pub recipient: Option<String>,
+    /// Где среди получателей адрес самого ящика.
+    pub addressed: Option<Addressed>,
+    pub action: RuleAction,
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            enabled: true,
+            sender: None,
+            domain: None,
+            subject: None,
+            label: None,
+            recipient: None,
+            addressed: None,
+            action: RuleAction::Suppress,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Addressed {
+    To,
+    Cc,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Не показывать, письмо остаётся непрочитанным.
+    Suppress,
+    MarkRead,
+    PlaySound { path: String },
+    /// Показать раньше остальных ожидающих.
+    RaisePriority,
+    Archive,
+    /// Не показывать, но учесть в счётчике в трее.
+    CountOnly,
+}
+
+/// Проверяет регулярные выражения до сохранения настроек.
+pub fn validate(rules: &[Rule]) -> Result<()> {
+    for rule in rules {
+        if let Some(subject) = &rule.subject {
+            subject_regex(subject).with_context(|| format!("правило «{}»: неверное выражение темы", rule.name))?;
+        }
+    }
+    Ok(())
+}
+
+fn subject_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+/// Включённые правила с заранее собранными выражениями, на один опрос.
+pub struct RuleSet<'a> {
+    rules: Vec<(&'a Rule, Option<Regex>)>,
+}
+
+impl<'a> RuleSet<'a> {
+    pub fn new(rules: &'a [Rule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter(|rule| rule.enabled)
+            .filter_map(|rule| match rule.subject.as_deref().map(subject_regex).transpose() {
+                Ok(regex) => Some((rule, regex)),
+                Err(err) => {
+                    warn!(rule = %rule.name, %err, "rules: правило пропущено, неверное выражение темы");
+                    None
+                }
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Имена меток нужны, только если какое-то правило смотрит на метки.
+    pub fn needs_labels(&self) -> bool {
+        self.rules.iter().any(|(rule, _)| rule.label.is_some())
+    }
+
+    /// `label_names` — имена меток по идентификатору.
+    pub fn evaluate(&self, notification: &GmailNotification, label_names: &HashMap<String, String>) -> Option<&'a Rule> {
+        if notification.summary {
+            return None;
+        }
+        self.rules
+            .iter()
+            .find(|(rule, subject)| matches(rule, subject.as_ref(), notification, label_names))
+            .map(|(rule, _)| *rule)
+    }
+}
+
+fn matches(
+    rule: &Rule,
+    subject: Option<&Regex>,
+    notification: &GmailNotification,
+    label_names: &HashMap<String, String>,
+) -> bool {
+    let sender = notification.from.as_ref().map(|from| from.email.as_str()).unwrap_or_default();
+    let in_list = |list: &[crate::headers::EmailAddress], email: &str| {
+        list.iter().any(|address| address.email.eq_ignore_ascii_case(email))
+    };
+
+    if rule.sender.as_ref().is_some_and(|expected| !sender.eq_ignore_ascii_case(expected)) {
+        return false;
+    }
+    if let Some(domain) = &rule.domain {
+        let domain = domain.trim_start_matches('@').to_lowercase();
+        let actual = sender.rsplit_once('@').map(|(_, d)| d.to_lowercase()).unwrap_or_default();
+        if actual != domain && !actual.ends_with(&format!(".{domain}")) {
+            return false;
+        }
+    }
+    if subject.is_some_and(|regex| !regex.is_match(&notification.subject)) {
+        return false;
+    }
+    if let Some(label) = &rule.label {
+        let found = notification.labels.iter().any(|id| {
+            id.eq_ignore_ascii_case(label)
+                || label_names.get(id).is_some_and(|name| name.eq_ignore_ascii_case(label))
+        });
+        if !found {
+            return false;
+        }
+    }
+    if let Some(recipient) = &rule.recipient {
+        if !in_list(&notification.to, recipient) && !in_list(&notification.cc, recipient) {
+            return false;
+        }
+    }
+    match rule.addressed {
+        Some(Addressed::To) if !in_list(&notification.to, &notification.account) => false,
+        Some(Addressed::Cc)
+            if in_list(&notification.to, &notification.account) || !in_list(&notification.cc, &notification.account) =>
+        {
+            false
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::EmailAddress;
+
+    fn address(email: &str) -> EmailAddress {
+        EmailAddress {
+            name: None,
+            email: email.into(),
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let mut n = GmailNotification::for_test("msg-1", "thread-1");
+        n.subject = "[Jenkins] Build #42 FAILED".into();
+        n.from = Some(address("ci@build.corp.example"));
+        n.to = vec![address("team@corp.example")];
+        n.cc = vec![address("Me@Example.com")];
+        n.labels = vec!["UNREAD".into(), "Label_2".into()];
+        let names = HashMap::from([("Label_2".to_string(), "Releases".to_string())]);
+
+        let rule = |name: &str, edit: fn(&mut Rule)| {
+            let mut rule = Rule {
+                name: name.into(),
+                ..Rule::default()
+            };
+            edit(&mut rule);
+            rule
+        };
+        let rules = vec![
+            rule("disabled", |r| r.enabled = false),
+            rule("other sender", |r| r.sender = Some("boss@example.com".into())),
+            rule("to me", |r| r.addressed = Some(Addressed::To)),
+            rule("wrong label", |r| r.label = Some("CATEGORY_PROMOTIONS".into())),
+            rule("ci", |r| {
+                r.domain = Some("corp.example".into());
+                r.subject = Some(r"build #\d+ failed".into());
+                r.label = Some("releases".into());
+                r.recipient = Some("TEAM@corp.example".into());
+                r.addressed = Some(Addressed::Cc);
+                r.action = RuleAction::PlaySound { path: "alarm.wav".into() };
+            }),
+            rule("catch-all", |r| r.action = RuleAction::CountOnly),
+        ];
+        let set = RuleSet::new(&rules);
+        assert!(set.needs_labels());
+        assert_eq!(set.evaluate(&n, &names).map(|r| r.name.as_str()), Some("ci"));
+
+        n.subject = "Build #42 fixed".into();
+        assert_eq!(set.evaluate(&n, &names).map(|r| r.name.as_str()), Some("catch-all"));
+        n.summary = true;
+        assert!(set.evaluate(&n, &names).is_none(), "summary is never filtered");
+
+        assert!(validate(&[rule("bad", |r| r.subject = Some("(".into()))]).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+
+const INLINE_PLACEHOLDER: &str = "#inline-image-";
+
+static IMG_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<img\b[^>]*>").unwrap());
+static SIZE_ATTR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?i)\b(width|height)\s*=\s*["']?\s*([0-9]+)(?:px)?\s*["']?"#).unwrap()
+});
+static SIZE_STYLE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\b(width|height)\s*:\s*([0-9]+)(?:px)?").unwrap());
+static SRC_ATTR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?i)(\ssrc\s*=\s*)(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap()
+});
+static HIDDEN_STYLE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)display\s*:\s*none|visibility\s*:\s*hidden").unwrap());
+
+/// Тело письма, безопасное для показа в окне уведомления.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SanitisedBody {
+    pub html: String,
+    /// Сколько внешних картинок и CSS-ссылок не загрузится.
+    pub blocked_resources: usize,
+    pub tracking_pixels: usize,
+}
+
+/// Убирает скрипты, обработчики событий, формы, фреймы и пиксели отслеживания.
+/// Внешние изображения остаются только при `allow_remote_images`.
+pub fn sanitise_html(html: &str, allow_remote_images: bool) -> SanitisedBody {
+    let mut tracking_pixels = 0;
+    // data:/cid: разрешены только в src картинок: прячем их за относительной ссылкой,
+    // которая проходит общую проверку схем, и возвращаем в attribute_filter
+    let mut inline = Vec::new();
+    let without_pixels = IMG_TAG.replace_all(html, |caps: &regex::Captures| {
+        if is_tracking_pixel(&caps[0]) {
+            tracking_pixels += 1;
+            return String::new();
+        }
+        SRC_ATTR
+            .replace(&caps[0], |src: &regex::Captures| {
+                let value = src.get(2).or(src.get(3)).or(src.get(4)).map_or("", |m| m.as_str());
+                if !is_inline_image(value) {
+                    return src[0].to_string();
+                }
+                inline.push(value.trim().to_string());
+                format!("{}\"{INLINE_PLACEHOLDER}{}\"", &src[1], inline.len() - 1)
+            })
+            .into_owned()
+    });
+    let inline = Arc::new(inline);
+
+    let blocked = Arc::new(AtomicUsize::new(0));
+    let counter = blocked.clone();
+    let html = ammonia::Builder::default()
+        .add_clean_content_tags(["iframe", "object", "embed", "noscript", "form"])
+        .add_generic_attributes(["style", "align", "valign", "width", "height", "bgcolor", "color"])
+        .add_tag_attributes("table", ["border", "cellpadding", "cellspacing"])
+        .add_tag_attributes("font", ["face", "size"])
+        .attribute_filter(move |element, attribute, value| {
+            match (element, attribute) {
+                ("img", "src") if value.starts_with(INLINE_PLACEHOLDER) => value[INLINE_PLACEHOLDER.len()..]
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| inline.get(index))
+                    .map(|url| Cow::Owned(url.clone())),
+                ("img", "src") if is_remote(value) && !allow_remote_images => {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+                // Стили умеют грузить ресурсы (url(), image-set(), экранирование в CSS),
+                // надёжно проверить это без разбора CSS нельзя — без картинок стили не оставляем
+                (_, "style") if !allow_remote_images => {
+                    let lower = value.to_ascii_lowercase();
+                    if lower.contains("url") || lower.contains("image") || lower.contains('\\') {
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None
+                }
+                _ => Some(Cow::Borrowed(value)),
+            }
+        })
+        .clean(&without_pixels)
+        .to_string();
+
+    SanitisedBody {
+        html,
+        blocked_resources: blocked.load(Ordering::Relaxed),
+        tracking_pixels,
+    }
+}
+
+/// Текстовое письмо: экранируем и сохраняем переносы строк, HTML-очистка тут не нужна.
+pub fn sanitise_text(text: &str) -> SanitisedBody {
+    let mut html = String::with_capacity(text.len() + 64);
+    html.push_str(r#"<pre style="white-space: pre-wrap; font-family: inherit">"#);
+    for ch in text.chars() {
+        match ch {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            _ => html.push(ch),
+        }
+    }
+    html.push_str("</pre>");
+    SanitisedBody {
+        html,
+        blocked_resources: 0,
+        tracking_pixels: 0,
+    }
+}
+
+fn is_inline_image(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    url.starts_with("data:image/") || url.starts_with("cid:")
+}
+
+fn is_remote(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    url.starts_with("http://") || url.starts_with("https://") || url.starts_with("//")
+}
+
+/// Невидимая картинка 1×1 (или скрытая стилем) — типичный пиксель отслеживания.
+fn is_tracking_pixel(tag: &str) -> bool {
+    if HIDDEN_STYLE.is_match(tag) {
+        return true;
+    }
+    let tiny = |re: &Regex, name: &str| {
+        re.captures_iter(tag).any(|caps| {
+            caps[1].eq_ignore_ascii_case(name) && caps[2].parse::<u32>().is_ok_and(|size| size <= 1)
+        })
+    };
+    let width = tiny(&SIZE_ATTR, "width") || tiny(&SIZE_STYLE, "width");
+    let height = tiny(&SIZE_ATTR, "height") || tiny(&SIZE_STYLE, "height");
+    width && height
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_active_content_and_remote_resources() {
+        let html = r#"
+            <p onclick="steal()">Hello <b>there</b></p>
+            <script>alert(1)</script>
+            <iframe src="https://evil.example"></iframe>
+            <form action="https://evil.example"><input name="password"></form>
+            <img src="https://cdn.example/logo.png" alt="logo">
+            <img src="https://t.example/open?id=1" width="1" height="1">
+            <div style="background: url(https://t.example/bg.png)">styled</div>
+            <img src="data:image/png;base64,AAAA">
+            <a href="data:text/html,<script>parent.__TAURI__</script>">link</a>
+            <a href="cid:part1">part</a>
+        "#;
+
+        let body = sanitise_html(html, false);
+        assert!(body.html.contains("<b>there</b>"));
+        for needle in ["onclick", "<script", "alert(1)", "<iframe", "<form", "<input", "cdn.example", "t.example"] {
+            assert!(!body.html.contains(needle), "{needle} must be stripped: {}", body.html);
+        }
+        assert!(body.html.contains(r#"<img src="data:image/png;base64,AAAA">"#), "{}", body.html);
+        assert!(!body.html.contains("data:text/html") && !body.html.contains("cid:"), "{}", body.html);
+        assert_eq!(body.blocked_resources, 2);
+        assert_eq!(body.tracking_pixels, 1);
+
+        // Экранирование в CSS обходит поиск подстроки url(
+        let escaped = sanitise_html(r#"<div style="background: u\72l(https://t.example/bg.png)">x</div>"#, false);
+        assert!(!escaped.html.contains("t.example"), "{}", escaped.html);
+        assert_eq!(escaped.blocked_resources, 1);
+
+        let allowed = sanitise_html(html, true);
+        assert!(allowed.html.contains("cdn.example/logo.png"));
+        assert!(allowed.html.contains("t.example/bg.png"), "styles stay when images are allowed");
+        assert!(!allowed.html.contains("t.example/open"), "pixels are removed even when images are allowed");
+        assert_eq!(allowed.blocked_resources, 0);
+    }
+
+    #[test]
+    fn keeps_plain_text_verbatim() {
+        let body = sanitise_text("From: Ann <ann@example.com>\n\n<b>not bold</b> & co");
+        assert!(body.html.contains("Ann &lt;ann@example.com&gt;\n\n&lt;b&gt;not bold&lt;/b&gt; &amp; co"), "{}", body.html);
+        assert!(body.html.starts_with("<pre"));
+    }
+}
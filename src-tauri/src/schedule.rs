@@ -0,0 +1,227 @@
+//! Рабочее расписание: вне рабочих окон уведомления идут без звука или копятся в дайджест,
+//! который показывается в начале следующего окна. Время — в локальном поясе системы.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, Days, Local, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuietMode {
+    /// Показывать без звука.
+    #[default]
+    Silence,
+    /// Не показывать, собрать в один дайджест к началу следующего окна.
+    Suppress,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkWindow {
+    pub days: Vec<Weekday>,
+    /// `HH:MM`; конец раньше начала — окно через полночь.
+    pub start: String,
+    pub end: String,
+}
+
+impl WorkWindow {
+    fn times(&self) -> Option<(NaiveTime, NaiveTime)> {
+        Some((parse_time(&self.start)?, parse_time(&self.end)?))
+    }
+
+    fn contains(&self, local: NaiveDateTime) -> bool {
+        let Some((start, end)) = self.times() else { return false };
+        let (day, time) = (local.weekday(), local.time());
+        if start < end {
+            self.days.contains(&day) && start <= time && time < end
+        } else {
+            (self.days.contains(&day) && time >= start) || (self.days.contains(&day.pred()) && time < end)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkSchedule {
+    pub enabled: bool,
+    pub mode: QuietMode,
+    pub windows: Vec<WorkWindow>,
+}
+
+impl Default for WorkSchedule {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: QuietMode::default(),
+            windows: vec![WorkWindow {
+                days: vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri],
+                start: "09:00".into(),
+                end: "19:00".into(),
+            }],
+        }
+    }
+}
+
+impl WorkSchedule {
+    pub fn validate(&self) -> Result<()> {
+        for window in &self.windows {
+            let (start, end) = window
+                .times()
+                .with_context(|| format!("неверное время окна {}–{}, нужно ЧЧ:ММ", window.start, window.end))?;
+            if start == end {
+                bail!("окно {}–{} пустое", window.start, window.end);
+            }
+        }
+        Ok(())
+    }
+
+    /// Сейчас нерабочее время (и расписание включено).
+    pub fn is_quiet(&self, now: DateTime<Utc>) -> bool {
+        self.is_quiet_in(now, &Local)
+    }
+
+    pub fn silences(&self, now: DateTime<Utc>) -> bool {
+        self.mode == QuietMode::Silence && self.is_quiet(now)
+    }
+
+    pub fn suppresses(&self, now: DateTime<Utc>) -> bool {
+        self.mode == QuietMode::Suppress && self.is_quiet(now)
+    }
+
+    /// Начало ближайшего рабочего окна после `now`.
+    pub fn next_start(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.next_start_in(now, &Local)
+    }
+
+    fn is_quiet_in<Tz: TimeZone>(&self, now: DateTime<Utc>, tz: &Tz) -> bool {
+        let local = now.with_timezone(tz).naive_local();
+        self.enabled && !self.windows.iter().any(|window| window.contains(local))
+    }
+
+    fn next_start_in<Tz: TimeZone>(&self, now: DateTime<Utc>, tz: &Tz) -> Option<DateTime<Utc>> {
+        if !self.enabled {
+            return None;
+        }
+        let today = now.with_timezone(tz).date_naive();
+        (0..=7)
+            .filter_map(|offset| today.checked_add_days(Days::new(offset)))
+            .flat_map(|date| {
+                self.windows
+                    .iter()
+                    .filter(move |window| window.days.contains(&date.weekday()))
+                    .filter_map(move |window| Some(resolve_local(date.and_time(window.times()?.0), tz)))
+            })
+            .filter(|at| *at > now)
+            .min()
+    }
+}
+
+/// Локальное время в момент: при переводе часов назад берём первое из двух,
+/// а попавшее в пропущенный час — первый существующий момент после него.
+pub(crate) fn resolve_local<Tz: TimeZone>(naive: NaiveDateTime, tz: &Tz) -> DateTime<Utc> {
+    (0..=24 * 60)
+        .find_map(|minutes| {
+            let probe = naive + chrono::Duration::minutes(minutes);
+            tz.from_local_datetime(&probe).earliest()
+        })
+        .map(|at| at.with_timezone(&Utc))
+        .unwrap_or_else(|| naive.and_utc())
+}
+
+pub(crate) fn parse_time(value: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, MappedLocalTime, NaiveDate};
+
+    /// Центральная Европа 2025: летнее время с 30 марта по 26 октября, переход в 01:00 UTC.
+    #[derive(Clone, Copy)]
+    struct Berlin;
+
+    impl Berlin {
+        fn offset_at(utc: &NaiveDateTime) -> FixedOffset {
+            let switch = |month, day| NaiveDate::from_ymd_opt(2025, month, day).unwrap().and_hms_opt(1, 0, 0).unwrap();
+            let hours = if *utc >= switch(3, 30) && *utc < switch(10, 26) { 2 } else { 1 };
+            FixedOffset::east_opt(hours * 3600).unwrap()
+        }
+    }
+
+    impl TimeZone for Berlin {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Berlin
+        }
+
+        fn offset_from_local_date(&self, local: &chrono::NaiveDate) -> MappedLocalTime<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(12, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> MappedLocalTime<FixedOffset> {
+            let candidates: Vec<FixedOffset> = [1, 2]
+                .into_iter()
+                .map(|hours| FixedOffset::east_opt(hours * 3600).unwrap())
+                .filter(|offset| Self::offset_at(&(*local - *offset)) == *offset)
+                .collect();
+            match candidates[..] {
+                [] => MappedLocalTime::None,
+                [single] => MappedLocalTime::Single(single),
+                [.., later] => MappedLocalTime::Ambiguous(later, candidates[0]),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &chrono::NaiveDate) -> FixedOffset {
+            Self::offset_at(&utc.and_hms_opt(12, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            Self::offset_at(utc)
+        }
+    }
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn finds_next_window_across_dst_transitions() {
+        let schedule = WorkSchedule {
+            enabled: true,
+            ..WorkSchedule::default()
+        };
+        // Пятница 19:30 по Берлину (летнее время) — тихо до понедельника 09:00
+        let friday = utc("2025-10-24T17:30:00Z");
+        assert!(schedule.is_quiet_in(friday, &Berlin));
+        // В воскресенье часы перевели назад: понедельник 09:00 — это уже 08:00 UTC
+        assert_eq!(schedule.next_start_in(friday, &Berlin), Some(utc("2025-10-27T08:00:00Z")));
+        assert!(!schedule.is_quiet_in(utc("2025-10-27T08:00:00Z"), &Berlin));
+        assert!(schedule.is_quiet_in(utc("2025-10-27T07:59:00Z"), &Berlin));
+
+        // Окно начинается в пропущенный при переводе вперёд час: 02:30 не существует
+        let night = WorkSchedule {
+            enabled: true,
+            mode: QuietMode::Suppress,
+            windows: vec![WorkWindow {
+                days: vec![Weekday::Sun],
+                start: "02:30".into(),
+                end: "01:00".into(),
+            }],
+        };
+        let saturday = utc("2025-03-29T12:00:00Z");
+        // Первый момент после разрыва — 03:00 летнего времени, 01:00 UTC
+        assert_eq!(night.next_start_in(saturday, &Berlin), Some(utc("2025-03-30T01:00:00Z")));
+        // Окно через полночь захватывает понедельник до 01:00
+        assert!(!night.is_quiet_in(utc("2025-03-30T22:30:00Z"), &Berlin));
+        assert!(night.is_quiet_in(utc("2025-03-30T23:30:00Z"), &Berlin));
+
+        assert!(WorkSchedule::default().next_start_in(friday, &Berlin).is_none(), "disabled");
+        let broken = WorkWindow {
+            days: vec![Weekday::Mon],
+            start: "9".into(),
+            end: "18:00".into(),
+        };
+        assert!(WorkSchedule { windows: vec![broken], ..WorkSchedule::default() }.validate().is_err());
+    }
+}
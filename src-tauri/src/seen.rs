@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::project_dirs;
+
+const SEEN_RETENTION_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationOutcome {
+    Read,
+    Dismissed,
+    Opened,
+    Archived,
+    Trashed,
+    /// Звёздочка, важность или метка: письмо осталось непрочитанным во входящих.
+    Labelled,
+    Replied,
+    /// Скрыто правилом без показа.
+    Filtered,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SeenEntry {
+    seen_at: DateTime<Utc>,
+    outcome: Option<NotificationOutcome>,
+    outcome_at: Option<DateTime<Utc>>,
+}
+
+/// Хранилище уже показанных писем, переживающее перезапуск приложения.
+pub struct SeenStore {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, SeenEntry>>,
+}
+
+impl SeenStore {
+    pub fn initialize() -> Result<Self> {
+        let proj = project_dirs()?;
+        let dir = proj.data_dir();
+        if !dir.exists() {
+            fs::create_dir_all(dir).context("Failed to create data directory")?;
+        }
+        Ok(Self::open(dir.join("seen.json")))
+    }
+
+    pub fn open(path: PathBuf) -> Self {
+        let mut entries = match load_entries(&path) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!(%err, path = %path.display(), "seen store is unreadable, starting empty");
+                HashMap::new()
+            }
+        };
+        prune(&mut entries, Utc::now());
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Письмо уже показывалось и не было с тех пор прочитано.
+    pub fn is_known(&self, id: &str) -> bool {
+        self.entries
+            .lock()
+            .get(id)
+            .is_some_and(|entry| entry.outcome != Some(NotificationOutcome::Read))
+    }
+
+    /// Отмечает показанными все письма опроса разом: файл переписывается один раз.
+    pub fn mark_seen_many<I>(&self, ids: I)
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let mut guard = self.entries.lock();
+        let now = Utc::now();
+        let mut changed = false;
+        for id in ids {
+            guard.insert(
+                id.as_ref().to_string(),
+                SeenEntry {
+                    seen_at: now,
+                    outcome: None,
+                    outcome_at: None,
+                },
+            );
+            changed = true;
+        }
+        if changed {
+            self.persist(&mut guard);
+        }
+    }
+
+    pub fn record_outcome(&self, id: &str, outcome: NotificationOutcome) {
+        let mut guard = self.entries.lock();
+        let now = Utc::now();
+        let entry = guard.entry(id.to_string()).or_insert(SeenEntry {
+            seen_at: now,
+            outcome: None,
+            outcome_at: None,
+        });
+        entry.outcome = Some(outcome);
+        entry.outcome_at = Some(now);
+        self.persist(&mut guard);
+    }
+
+    pub fn forget(&self, id: &str) {
+        let mut guard = self.entries.lock();
+        if guard.remove(id).is_some() {
+            self.persist(&mut guard);
+        }
+    }
+
+    fn persist(&self, entries: &mut HashMap<String, SeenEntry>) {
+        prune(entries, Utc::now());
+        if let Err(err) = save_entries(&self.path, entries) {
+            warn!(%err, path = %self.path.display(), "failed to persist seen store");
+        }
+    }
+}
+
+fn prune(entries: &mut HashMap<String, SeenEntry>, now: DateTime<Utc>) {
+    let cutoff = now - Duration::days(SEEN_RETENTION_DAYS);
+    entries.retain(|_, entry| entry.outcome_at.unwrap_or(entry.seen_at) >= cutoff);
+}
+
+fn load_entries(path: &Path) -> Result<HashMap<String, SeenEntry>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let bytes = fs::read(path).context("Failed to read seen store")?;
+    serde_json::from_slice(&bytes).context("Invalid seen store file")
+}
+
+fn save_entries(path: &Path, entries: &HashMap<String, SeenEntry>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create parent directory")?;
+    }
+    let json = serde_json::to_vec(entries).context("Failed to serialise seen store")?;
+    // Пишем рядом и переименовываем: сбой посреди записи не оставит обрезанный файл
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json).context("Failed to write seen store")?;
+    fs::rename(&tmp, path).context("Failed to replace seen store")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn survives_reopen_and_expires_old_entries() {
+        let path = std::env::temp_dir().join(format!("seen-{}.json", uuid::Uuid::new_v4()));
+        let store = SeenStore::open(path.clone());
+        store.mark_seen_many(["fresh", "read", "stale"]);
+        store.record_outcome("read", NotificationOutcome::Read);
+        store.entries.lock().get_mut("stale").unwrap().seen_at =
+            Utc::now() - Duration::days(SEEN_RETENTION_DAYS + 1);
+        store.record_outcome("fresh", NotificationOutcome::Dismissed);
+
+        assert!(!path.with_extension("json.tmp").exists(), "temporary file is renamed into place");
+
+        let reopened = SeenStore::open(path.clone());
+        assert!(reopened.is_known("fresh"));
+        assert!(!reopened.is_known("read"), "read messages may notify again");
+        assert!(!reopened.is_known("stale"), "expired entries are pruned");
+        reopened.forget("fresh");
+        assert!(!reopened.is_known("fresh"));
+
+        let _ = fs::remove_file(path);
+    }
+}
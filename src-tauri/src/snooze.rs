@@ -0,0 +1,210 @@
+//! Отложенный показ: общая пауза уведомлений и отдельные письма, которые вернутся в срок.
+//! Сроки — по настенным часам; состояние хранится на диске и переживает перезапуск.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Local, TimeZone, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::project_dirs;
+use crate::gmail::GmailNotification;
+use crate::schedule::{parse_time, resolve_local};
+
+/// До какого момента отложить.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SnoozeTarget {
+    Minutes { minutes: u64 },
+    /// Завтра в указанное местное время, `HH:MM`.
+    Tomorrow { time: String },
+    /// Конкретный момент, например конец встречи.
+    Until { at: DateTime<Utc> },
+}
+
+impl SnoozeTarget {
+    pub fn deadline(&self, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        self.deadline_in(now, &Local)
+    }
+
+    fn deadline_in<Tz: TimeZone>(&self, now: DateTime<Utc>, tz: &Tz) -> Result<DateTime<Utc>> {
+        let at = match self {
+            Self::Minutes { minutes } => now + Duration::minutes((*minutes).clamp(1, 7 * 24 * 60) as i64),
+            Self::Tomorrow { time } => {
+                let time = parse_time(time).with_context(|| format!("неверное время {time}, нужно ЧЧ:ММ"))?;
+                let tomorrow = now
+                    .with_timezone(tz)
+                    .date_naive()
+                    .succ_opt()
+                    .context("дата вне допустимого диапазона")?;
+                resolve_local(tomorrow.and_time(time), tz)
+            }
+            Self::Until { at } => *at,
+        };
+        if at <= now {
+            bail!("это время уже прошло");
+        }
+        Ok(at)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnoozedMessage {
+    pub until: DateTime<Utc>,
+    pub notification: GmailNotification,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SnoozeState {
+    /// Общая пауза показа.
+    until: Option<DateTime<Utc>>,
+    messages: Vec<SnoozedMessage>,
+}
+
+/// Сработавшие сроки: закончилась ли общая пауза и какие письма пора вернуть.
+#[derive(Debug, Default)]
+pub struct SnoozeDue {
+    pub resumed: bool,
+    pub messages: Vec<GmailNotification>,
+}
+
+pub struct SnoozeStore {
+    path: PathBuf,
+    state: Mutex<SnoozeState>,
+}
+
+impl SnoozeStore {
+    pub fn initialize() -> Result<Self> {
+        let proj = project_dirs()?;
+        let dir = proj.data_dir();
+        if !dir.exists() {
+            fs::create_dir_all(dir).context("Failed to create data directory")?;
+        }
+        Ok(Self::open(dir.join("snooze.json")))
+    }
+
+    pub fn open(path: PathBuf) -> Self {
+        let state = load_state(&path).unwrap_or_else(|err| {
+            warn!(%err, path = %path.display(), "snooze store is unreadable, starting empty");
+            SnoozeState::default()
+        });
+        Self {
+            path,
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn until(&self) -> Option<DateTime<Utc>> {
+        self.state.lock().until
+    }
+
+    pub fn set_until(&self, until: Option<DateTime<Utc>>) {
+        let mut state = self.state.lock();
+        state.until = until;
+        self.persist(&state);
+    }
+
+    /// Повторное откладывание того же письма переносит срок.
+    pub fn snooze_message(&self, notification: GmailNotification, until: DateTime<Utc>) {
+        let mut state = self.state.lock();
+        state.messages.retain(|m| m.notification.id != notification.id);
+        state.messages.push(SnoozedMessage { until, notification });
+        self.persist(&state);
+    }
+
+    pub fn messages(&self) -> Vec<SnoozedMessage> {
+        self.state.lock().messages.clone()
+    }
+
+    /// Ближайший срок — общей паузы или отложенного письма.
+    pub fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        let state = self.state.lock();
+        state.until.into_iter().chain(state.messages.iter().map(|m| m.until)).min()
+    }
+
+    pub fn take_due(&self, now: DateTime<Utc>) -> SnoozeDue {
+        let mut state = self.state.lock();
+        let mut due = SnoozeDue::default();
+        if state.until.is_some_and(|until| until <= now) {
+            state.until = None;
+            due.resumed = true;
+        }
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut state.messages)
+            .into_iter()
+            .partition(|m| m.until <= now);
+        state.messages = waiting;
+        due.messages = ready.into_iter().map(|m| m.notification).collect();
+        if due.resumed || !due.messages.is_empty() {
+            self.persist(&state);
+        }
+        due
+    }
+
+    fn persist(&self, state: &SnoozeState) {
+        if let Err(err) = save_state(&self.path, state) {
+            warn!(%err, path = %self.path.display(), "failed to persist snooze store");
+        }
+    }
+}
+
+fn load_state(path: &Path) -> Result<SnoozeState> {
+    if !path.exists() {
+        return Ok(SnoozeState::default());
+    }
+    let bytes = fs::read(path).context("Failed to read snooze store")?;
+    serde_json::from_slice(&bytes).context("Invalid snooze store file")
+}
+
+fn save_state(path: &Path, state: &SnoozeState) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create parent directory")?;
+    }
+    let json = serde_json::to_vec(state).context("Failed to serialise snooze store")?;
+    fs::write(path, json).context("Failed to write snooze store")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn deadlines_survive_reopen() {
+        let now = utc("2025-10-14T22:30:00Z");
+        let msk = FixedOffset::east_opt(3 * 3600).unwrap();
+        // В Москве уже 15-е, «завтра» — 16-е
+        let tomorrow = SnoozeTarget::Tomorrow { time: "09:00".into() };
+        assert_eq!(tomorrow.deadline_in(now, &msk).unwrap(), utc("2025-10-16T06:00:00Z"));
+        assert!(SnoozeTarget::Until { at: now }.deadline_in(now, &msk).is_err());
+        assert!(SnoozeTarget::Tomorrow { time: "9".into() }.deadline_in(now, &msk).is_err());
+
+        let path = std::env::temp_dir().join(format!("snooze-{}.json", uuid::Uuid::new_v4()));
+        let store = SnoozeStore::open(path.clone());
+        let message = GmailNotification::for_test("msg-1", "thread-1");
+        store.set_until(Some(now + Duration::minutes(20)));
+        store.snooze_message(message.clone(), now + Duration::minutes(5));
+        store.snooze_message(message, now + Duration::minutes(10));
+
+        let reopened = SnoozeStore::open(path.clone());
+        assert_eq!(reopened.messages().len(), 1, "snoozing again moves the deadline");
+        assert_eq!(reopened.next_deadline(), Some(now + Duration::minutes(10)));
+
+        let due = reopened.take_due(now + Duration::minutes(15));
+        assert!(!due.resumed);
+        assert_eq!(due.messages.len(), 1);
+        assert_eq!(due.messages[0].id, "msg-1");
+        let due = reopened.take_due(now + Duration::minutes(20));
+        assert!(due.resumed && due.messages.is_empty());
+        assert!(SnoozeStore::open(path.clone()).next_deadline().is_none());
+
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,119 @@
+//! Шаблоны быстрых ответов. Хранятся в настройках, подставляются из полей уведомления:
+//! `{sender_name}`, `{sender_email}`, `{subject}`, `{date}`, `{account}`.
+
+use std::fmt::Display;
+
+use chrono::{Local, TimeZone};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+use crate::gmail::GmailNotification;
+
+static VARIABLE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{([a-z_]+)\}").unwrap());
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplyTemplate {
+    pub name: String,
+    pub body: String,
+}
+
+impl ReplyTemplate {
+    fn new(name: &str, body: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    pub fn render(&self, notification: &GmailNotification) -> String {
+        self.render_in(notification, &Local)
+    }
+
+    /// Неизвестные переменные оставляем как есть — это скорее опечатка в шаблоне,
+    /// и её лучше увидеть в отправленном тексте, чем потерять молча.
+    fn render_in<Tz: TimeZone>(&self, notification: &GmailNotification, tz: &Tz) -> String
+    where
+        Tz::Offset: Display,
+    {
+        let from = notification.from.as_ref();
+        VARIABLE
+            .replace_all(&self.body, |caps: &Captures| match &caps[1] {
+                "sender_name" => from
+                    .map(|from| match &from.name {
+                        Some(name) => name.clone(),
+                        None => from.email.split('@').next().unwrap_or_default().to_string(),
+                    })
+                    .unwrap_or_default(),
+                "sender_email" => from.map(|from| from.email.clone()).unwrap_or_default(),
+                "subject" => notification.subject.clone(),
+                "date" => notification
+                    .received_at
+                    .map(|at| at.with_timezone(tz).format("%d.%m.%Y").to_string())
+                    .unwrap_or_default(),
+                "account" => notification.account.clone(),
+                _ => caps[0].to_string(),
+            })
+            .into_owned()
+    }
+}
+
+pub fn default_templates() -> Vec<ReplyTemplate> {
+    vec![
+        ReplyTemplate::new(
+            "Получил",
+            "Здравствуйте, {sender_name}!\n\nПолучил ваше письмо «{subject}», отвечу позже.",
+        ),
+        ReplyTemplate::new("Спасибо", "{sender_name}, спасибо!"),
+    ]
+}
+
+/// Пустые имена и тела отбрасываем, из одинаковых имён оставляем первое.
+pub fn normalise(templates: Vec<ReplyTemplate>) -> Vec<ReplyTemplate> {
+    let mut result: Vec<ReplyTemplate> = Vec::with_capacity(templates.len());
+    for template in templates {
+        let name = template.name.trim();
+        if name.is_empty() || template.body.trim().is_empty() || result.iter().any(|t| t.name == name) {
+            continue;
+        }
+        result.push(ReplyTemplate::new(name, &template.body));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::EmailAddress;
+    use chrono::{DateTime, FixedOffset, Utc};
+
+    #[test]
+    fn renders_variables_from_notification() {
+        let mut notification = GmailNotification::for_test("msg-1", "thread-1");
+        notification.subject = "Счёт за октябрь".into();
+        notification.from = Some(EmailAddress {
+            name: Some("Иван Петров".into()),
+            email: "ivan@example.com".into(),
+        });
+        // 22:30 UTC — в Москве уже следующий день
+        notification.received_at = Some(DateTime::parse_from_rfc3339("2025-10-14T22:30:00Z").unwrap().with_timezone(&Utc));
+        let msk = FixedOffset::east_opt(3 * 3600).unwrap();
+
+        let template = ReplyTemplate::new("t", "{sender_name} <{sender_email}>: «{subject}» от {date}, {unknown} {account}");
+        assert_eq!(
+            template.render_in(&notification, &msk),
+            "Иван Петров <ivan@example.com>: «Счёт за октябрь» от 15.10.2025, {unknown} me@example.com"
+        );
+
+        notification.from.as_mut().unwrap().name = None;
+        assert_eq!(ReplyTemplate::new("t", "{sender_name}").render_in(&notification, &msk), "ivan");
+
+        let templates = normalise(vec![
+            ReplyTemplate::new(" Ок ", "ok"),
+            ReplyTemplate::new("Ок", "другое"),
+            ReplyTemplate::new("", "no name"),
+            ReplyTemplate::new("Пусто", "  "),
+        ]);
+        assert_eq!(templates, [ReplyTemplate::new("Ок", "ok")]);
+    }
+}